@@ -0,0 +1,65 @@
+//! Compares calling `process` on each element against `process_batch`.
+//! Run with `cargo bench --bench batch`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use streamo::bloom::DynBloomFilter;
+use streamo::distinct::{FlajoletMartin, HyperLogLog};
+use streamo::high_freq::{CountMin, DynCountMin};
+use streamo::StreamProcessor;
+
+const ITEMS: usize = 1 << 18;
+const BATCH: usize = 1024;
+const RUNS: usize = 7;
+
+/// Fastest of several runs, which is the least disturbed by other work on the machine.
+fn time<T, P: StreamProcessor<T>>(items: &[T], f: impl Fn(&mut P, &[T])) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut p = P::new();
+            let start = Instant::now();
+            for chunk in items.chunks(BATCH) {
+                f(&mut p, chunk);
+            }
+            let elapsed = start.elapsed();
+            black_box(&p);
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn compare<T: Clone, P: StreamProcessor<T>>(name: &str, items: &[T]) {
+    let single = time::<T, P>(items, |p, chunk| {
+        for v in chunk {
+            p.process(v.clone());
+        }
+    });
+    let batch = time::<T, P>(items, |p, chunk| p.process_batch(chunk));
+    let per_item = |d: Duration| d.as_nanos() as f64 / items.len() as f64;
+    println!(
+        "{:<24} process: {:>7.2} ns/item  process_batch: {:>7.2} ns/item  speedup: {:.2}x",
+        name,
+        per_item(single),
+        per_item(batch),
+        single.as_secs_f64() / batch.as_secs_f64(),
+    );
+}
+
+fn main() {
+    let ints = (0..ITEMS as u64)
+        .map(|i| i.wrapping_mul(0x9e37_79b9))
+        .collect::<Vec<_>>();
+    let urls = ints
+        .iter()
+        .map(|i| format!("https://example.com/user/{}/profile", i % 100_000))
+        .collect::<Vec<_>>();
+
+    compare::<_, CountMin<u64>>("CountMin<u64>", &ints);
+    let urls = urls.iter().map(String::as_str).collect::<Vec<_>>();
+    compare::<_, CountMin<&str>>("CountMin<&str>", &urls);
+    compare::<_, DynCountMin<u64>>("DynCountMin<u64>", &ints);
+    compare::<_, DynCountMin<&str>>("DynCountMin<&str>", &urls);
+    compare::<_, DynBloomFilter<u64>>("DynBloomFilter<u64>", &ints);
+    compare::<_, FlajoletMartin<u64>>("FlajoletMartin<u64>", &ints);
+    compare::<_, HyperLogLog<u64>>("HyperLogLog<u64>", &ints);
+}
@@ -1,7 +1,58 @@
-use super::StreamProcessor;
+use super::{Merge, StreamProcessor};
 use std::array;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Results which can be averaged, so that their estimates can be boosted.
+pub trait Numeric: Copy {
+    fn to_f64(self) -> f64;
+    /// Converts back from an average, rounding for integer types.
+    fn from_f64(v: f64) -> Self;
+}
+
+macro_rules! impl_numeric {
+  ($($t:ty: $round:expr),* $(,)?) => {
+    $(impl Numeric for $t {
+        fn to_f64(self) -> f64 {
+            self as f64
+        }
+        fn from_f64(v: f64) -> Self {
+            $round(v) as $t
+        }
+    })*
+  };
+}
+
+impl_numeric!(
+  usize: f64::round,
+  u32: f64::round,
+  u64: f64::round,
+  f32: std::convert::identity,
+  f64: std::convert::identity,
+);
+
+/// Returns the median of some values, averaging the middle two if there is an even number.
+pub(crate) fn median(vals: &mut [f64]) -> f64 {
+    assert!(!vals.is_empty());
+    vals.sort_unstable_by(f64::total_cmp);
+    let mid = vals.len() / 2;
+    if vals.len() % 2 == 0 {
+        (vals[mid - 1] + vals[mid]) / 2.0
+    } else {
+        vals[mid]
+    }
+}
 
 /// Gets a better approximation of an approximation by taking the median of many instances.
+/// Keeps `M` groups of `N` instances, and returns the median of the groups' means.
+/// Averaging shrinks the variance by `N`, and the median makes a bad mean unlikely, so for an
+/// estimator with variance `v`, `N = O(v / eps^2)` and `M = O(log(1 / delta))` give an
+/// `eps` error with probability `1 - delta`.
+///
+/// Every instance is created separately with `S::new`, so each draws its own hash seeds or
+/// random generator. Cloning one instance instead would make all of them agree, defeating the
+/// purpose.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct MedianOfMeans<S, const N: usize, const M: usize> {
     groups: [[S; N]; M],
@@ -9,8 +60,12 @@ pub struct MedianOfMeans<S, const N: usize, const M: usize> {
 
 impl<T: Copy, S: StreamProcessor<T>, const N: usize, const M: usize> StreamProcessor<T>
     for MedianOfMeans<S, N, M>
+where
+    S::Result: Numeric,
 {
     fn new() -> Self {
+        assert_ne!(N, 0);
+        assert_ne!(M, 0);
         let groups = array::from_fn(|_| array::from_fn(|_| S::new()));
         Self { groups }
     }
@@ -23,9 +78,22 @@ impl<T: Copy, S: StreamProcessor<T>, const N: usize, const M: usize> StreamProce
     }
     type Result = S::Result;
     type Args = S::Args;
-    fn query(&self, _args: &S::Args) -> S::Result {
-        // TODO need to make this work with numeric types.
-        todo!();
+    fn query(&self, args: &S::Args) -> S::Result {
+        let mut means = self.groups.each_ref().map(|group| {
+            let sum: f64 = group.iter().map(|sub| sub.query(args).to_f64()).sum();
+            sum / N as f64
+        });
+        S::Result::from_f64(median(&mut means))
+    }
+}
+
+impl<S: Merge, const N: usize, const M: usize> Merge for MedianOfMeans<S, N, M> {
+    fn merge(&mut self, other: Self) {
+        for (a, b) in self.groups.iter_mut().zip(other.groups) {
+            for (a, b) in a.iter_mut().zip(b) {
+                a.merge(b);
+            }
+        }
     }
 }
 
@@ -53,3 +121,469 @@ impl<T: Copy, S: StreamProcessor<T, Args = T, Result = bool>, const N: usize> St
         self.subs.iter().all(|sub| sub.query(args))
     }
 }
+
+impl<S: Merge, const N: usize> Merge for BoolGroup<S, N> {
+    fn merge(&mut self, other: Self) {
+        for (a, b) in self.subs.iter_mut().zip(other.subs) {
+            a.merge(b);
+        }
+    }
+}
+
+/// Runs several processors over the same stream in one pass, where each element is cloned to
+/// all of them. Queries take a tuple of every member's arguments, and return a tuple of their
+/// results.
+macro_rules! impl_tuple {
+  ($($p:ident $i:tt),+) => {
+    impl<T: Clone, $($p: StreamProcessor<T>),+> StreamProcessor<T> for ($($p,)+) {
+        fn new() -> Self {
+            ($($p::new(),)+)
+        }
+        fn process(&mut self, v: T) {
+            $(self.$i.process(v.clone());)+
+        }
+        fn process_batch(&mut self, vs: &[T]) {
+            $(self.$i.process_batch(vs);)+
+        }
+        type Result = ($($p::Result,)+);
+        type Args = ($($p::Args,)+);
+        fn query(&self, args: &Self::Args) -> Self::Result {
+            ($(self.$i.query(&args.$i),)+)
+        }
+    }
+
+    impl<$($p: Merge),+> Merge for ($($p,)+) {
+        fn merge(&mut self, other: Self) {
+            $(self.$i.merge(other.$i);)+
+        }
+    }
+  };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// Function applied to each element by `Map` and `Filter`. Closures implement it, but
+/// `StreamProcessor::new` has to create the function as well, so adapters built by `new` need a
+/// named `Default` type, usually declared with `projection!`. Adapters over closures are built
+/// with `with` and fed with their own `process`.
+pub trait Project<T> {
+    type Output;
+    fn project(&self, v: T) -> Self::Output;
+}
+
+impl<T, O, F: Fn(T) -> O> Project<T> for F {
+    type Output = O;
+    fn project(&self, v: T) -> O {
+        self(v)
+    }
+}
+
+/// Declares a unit struct which implements `Project`, for use with `Map` and `Filter`.
+/// ```
+/// use streamo::adapters::{Filter, Map};
+/// use streamo::count::ExactCounter;
+/// use streamo::projection;
+/// use streamo::StreamProcessor;
+///
+/// #[derive(Clone)]
+/// struct Request {
+///     latency: u64,
+///     ok: bool,
+/// }
+/// projection!(Latency: Request => u64 = |r| r.latency);
+/// projection!(pub Failed: &Request => bool = |r| !r.ok);
+///
+/// let reqs = vec![Request { latency: 5, ok: true }, Request { latency: 9, ok: false }];
+/// let failed = Filter::<ExactCounter, Failed>::apply(reqs.clone().into_iter(), &());
+/// assert_eq!(failed, 1);
+/// let total = Map::<ExactCounter, Latency>::apply(reqs.into_iter(), &());
+/// assert_eq!(total, 2);
+/// ```
+#[macro_export]
+macro_rules! projection {
+  ($vis:vis $name:ident: $in:ty => $out:ty = |$arg:pat_param| $body:expr) => {
+    #[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+    $vis struct $name;
+
+    impl $crate::adapters::Project<$in> for $name {
+        type Output = $out;
+        fn project(&self, $arg: $in) -> $out {
+            $body
+        }
+    }
+  };
+}
+
+/// Feeds the inner processor `f(t)` for each element `t` of the stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Map<P, F> {
+    inner: P,
+    f: F,
+}
+
+impl<P, F> Map<P, F> {
+    /// Wraps `inner`, so that it processes every element after `f` is applied to it.
+    pub fn with(inner: P, f: F) -> Self {
+        Self { inner, f }
+    }
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+    /// Processes `f(v)`, also where `f` is a closure, which `StreamProcessor` does not allow.
+    pub fn process<T>(&mut self, v: T)
+    where
+        F: Project<T>,
+        P: StreamProcessor<F::Output>,
+    {
+        self.inner.process(self.f.project(v));
+    }
+}
+
+impl<T, F: Project<T> + Default, P: StreamProcessor<F::Output>> StreamProcessor<T> for Map<P, F> {
+    fn new() -> Self {
+        Self::with(P::new(), F::default())
+    }
+    fn process(&mut self, v: T) {
+        Map::process(self, v);
+    }
+    type Result = P::Result;
+    type Args = P::Args;
+    fn query(&self, args: &P::Args) -> P::Result {
+        self.inner.query(args)
+    }
+}
+
+impl<P: Merge, F> Merge for Map<P, F> {
+    fn merge(&mut self, other: Self) {
+        self.inner.merge(other.inner);
+    }
+}
+
+/// Only feeds the inner processor elements `t` for which `f(&t)` holds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Filter<P, F> {
+    inner: P,
+    f: F,
+}
+
+impl<P, F> Filter<P, F> {
+    /// Wraps `inner`, so that it only processes elements which pass `f`.
+    pub fn with(inner: P, f: F) -> Self {
+        Self { inner, f }
+    }
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+    /// Processes `v` if `f(&v)` holds, also where `f` is a closure, which `StreamProcessor`
+    /// does not allow.
+    pub fn process<T>(&mut self, v: T)
+    where
+        F: for<'a> Project<&'a T, Output = bool>,
+        P: StreamProcessor<T>,
+    {
+        if self.f.project(&v) {
+            self.inner.process(v);
+        }
+    }
+}
+
+impl<T, F, P: StreamProcessor<T>> StreamProcessor<T> for Filter<P, F>
+where
+    F: for<'a> Project<&'a T, Output = bool> + Default,
+{
+    fn new() -> Self {
+        Self::with(P::new(), F::default())
+    }
+    fn process(&mut self, v: T) {
+        Filter::process(self, v);
+    }
+    type Result = P::Result;
+    type Args = P::Args;
+    fn query(&self, args: &P::Args) -> P::Result {
+        self.inner.query(args)
+    }
+}
+
+impl<P: Merge, F> Merge for Filter<P, F> {
+    fn merge(&mut self, other: Self) {
+        self.inner.merge(other.inner);
+    }
+}
+
+/// Routes each `(key, value)` pair to a processor for its key, which is created when the key is
+/// first seen. Queries take a key and the arguments for its processor, returning `None` for keys
+/// without a group.
+///
+/// Every group is cloned from one prototype, so the groups of clones of a `GroupBy` share their
+/// hash functions and can be merged.
+///
+/// The number of groups can be capped with `with_max_keys`, which admits keys like
+/// `MisraGries`: each group counts its elements, and an element of a new key arriving while all
+/// groups are taken is dropped, decrementing every count instead. Groups whose count reaches zero
+/// are evicted. Any key with more than `n / (max_keys + 1)` of the `n` elements keeps its group,
+/// though that group may have missed up to that many of its elements.
+#[derive(Clone, Debug)]
+pub struct GroupBy<K, P> {
+    prototype: P,
+    groups: HashMap<K, (usize, P)>,
+    max_keys: Option<usize>,
+}
+
+impl<K, P> GroupBy<K, P> {
+    /// Creates a `GroupBy` without a cap on keys, whose groups start as clones of `prototype`.
+    pub fn with_prototype(prototype: P) -> Self {
+        Self {
+            prototype,
+            groups: HashMap::new(),
+            max_keys: None,
+        }
+    }
+    /// Creates a `GroupBy` keeping at most `max_keys` groups, cloned from `prototype`.
+    pub fn with_max_keys(prototype: P, max_keys: usize) -> Self {
+        assert_ne!(max_keys, 0);
+        Self {
+            max_keys: Some(max_keys),
+            ..Self::with_prototype(prototype)
+        }
+    }
+    /// Number of groups currently kept.
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+    /// Returns the processor for a key, to query it directly.
+    pub fn get(&self, k: &K) -> Option<&P>
+    where
+        K: Hash + Eq,
+    {
+        self.groups.get(k).map(|(_, p)| p)
+    }
+    /// Iterates over every key with its processor, in arbitrary order.
+    pub fn groups(&self) -> impl Iterator<Item = (&K, &P)> {
+        self.groups.iter().map(|(k, (_, p))| (k, p))
+    }
+}
+
+impl<K: Hash + Eq, P: Clone> GroupBy<K, P> {
+    /// Routes an element of `k` like `process`, where `update` feeds it to the group of `k` and
+    /// tells whether it adds to the count of the key. For a key without a group, `fresh` tells
+    /// whether the element is admitted or charged against the other keys at all.
+    pub(crate) fn process_weighted(
+        &mut self,
+        k: K,
+        update: impl FnOnce(&mut P) -> bool,
+        fresh: impl FnOnce() -> bool,
+    ) {
+        let len = self.groups.len();
+        match self.groups.entry(k) {
+            Entry::Occupied(mut o) => {
+                let (count, p) = o.get_mut();
+                if update(p) {
+                    *count += 1;
+                }
+            }
+            Entry::Vacant(_) if !fresh() => {}
+            Entry::Vacant(e) if self.max_keys.map_or(true, |m| len < m) => {
+                let mut p = self.prototype.clone();
+                update(&mut p);
+                e.insert((1, p));
+            }
+            Entry::Vacant(_) => {
+                self.groups.retain(|_, (count, _)| {
+                    *count -= 1;
+                    *count != 0
+                });
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, P: StreamProcessor<V> + Clone> StreamProcessor<(K, V)> for GroupBy<K, P> {
+    fn new() -> Self {
+        Self::with_prototype(P::new())
+    }
+    fn process(&mut self, (k, v): (K, V)) {
+        let update = |p: &mut P| {
+            p.process(v);
+            true
+        };
+        self.process_weighted(k, update, || true);
+    }
+    type Result = Option<P::Result>;
+    type Args = (K, P::Args);
+    fn query(&self, (k, args): &(K, P::Args)) -> Option<P::Result> {
+        self.get(k).map(|p| p.query(args))
+    }
+}
+
+impl<K: Hash + Eq, P: Merge> Merge for GroupBy<K, P> {
+    /// Merges the groups of each key. With a cap, the counts are then reduced like in
+    /// `MisraGries`, evicting the groups of all but the heaviest keys.
+    fn merge(&mut self, other: Self) {
+        for (k, (count, p)) in other.groups {
+            match self.groups.entry(k) {
+                Entry::Occupied(mut o) => {
+                    let (c, q) = o.get_mut();
+                    *c += count;
+                    q.merge(p);
+                }
+                Entry::Vacant(e) => {
+                    e.insert((count, p));
+                }
+            }
+        }
+        let max_keys = match self.max_keys {
+            Some(m) if self.groups.len() > m => m,
+            _ => return,
+        };
+        let mut counts = self.groups.values().map(|(c, _)| *c).collect::<Vec<_>>();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        let cutoff = counts[max_keys];
+        self.groups.retain(|_, (c, _)| {
+            *c = c.saturating_sub(cutoff);
+            *c != 0
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_adapters {
+    use super::{Filter, GroupBy, Map, MedianOfMeans};
+    use crate::count::{ExactCounter, MorrisCounter};
+    use crate::distinct::HyperLogLog;
+    use crate::estimate::Estimator;
+    use crate::high_freq::{DynCountMin, Majority};
+    use crate::quantile::Quantile;
+    use crate::Merge;
+    use crate::StreamProcessor;
+
+    #[test]
+    fn median_of_exact() {
+        let count = MedianOfMeans::<ExactCounter, 3, 4>::apply(0..1000, &());
+        assert_eq!(count, 1000);
+    }
+
+    #[test]
+    fn tuple_one_pass() {
+        let stream = (0..1000).map(|i| if i % 2 == 0 { 7 } else { i % 50 });
+        type All = (
+            ExactCounter,
+            HyperLogLog<u32>,
+            Majority<u32>,
+            DynCountMin<u32>,
+        );
+        let (count, distinct, majority, sevens) = All::apply(stream, &((), (), (), 7));
+        assert_eq!(count, 1000);
+        assert!((23..=27).contains(&distinct));
+        assert_eq!(majority, vec![7]);
+        assert!(sevens >= 500);
+    }
+
+    struct Request {
+        endpoint: &'static str,
+        latency: u64,
+    }
+    projection!(Latency: Request => u64 = |r| r.latency);
+    projection!(IsSearch: &Request => bool = |r| r.endpoint == "/search");
+
+    #[test]
+    fn map_and_filter() {
+        let reqs = || {
+            (0..1000u64).map(|i| Request {
+                endpoint: if i % 4 == 0 { "/search" } else { "/home" },
+                latency: i,
+            })
+        };
+        let mut q = Map::<Quantile<u64>, Latency>::new();
+        for r in reqs() {
+            q.process(r);
+        }
+        let e = q.inner().estimate(&500, 0.99);
+        assert!(e.contains(500.0), "{:?}", e);
+
+        let searches = Filter::<ExactCounter, IsSearch>::apply(reqs(), &());
+        assert_eq!(searches, 250);
+
+        let mut by_latency = Map::with(DynCountMin::<u64>::with_dimensions(2048, 4), Latency);
+        for r in reqs() {
+            by_latency.process(r);
+        }
+        assert!(by_latency.query(&7) >= 1);
+    }
+
+    #[test]
+    fn closure_projections() {
+        let cutoff = 900;
+        let mut slow = Filter::with(HyperLogLog::<u64>::new(), |&l: &u64| l >= cutoff);
+        let mut buckets = Map::with(
+            DynCountMin::<u64>::with_dimensions(2048, 4),
+            |r: Request| r.latency / 100,
+        );
+        for i in 0..1000u64 {
+            slow.process(i);
+            buckets.process(Request {
+                endpoint: "/home",
+                latency: i,
+            });
+        }
+        let distinct = slow.inner().query(&());
+        assert!((95..=105).contains(&distinct), "{}", distinct);
+        assert!(buckets.inner().query(&3) >= 100);
+    }
+
+    #[test]
+    fn group_by() {
+        let visits = (0..3000u32).map(|i| (["de", "fr", "us"][i as usize % 3], i % 300));
+        let mut by_country = GroupBy::<_, HyperLogLog<u32>>::new();
+        for v in visits {
+            by_country.process(v);
+        }
+        assert_eq!(by_country.len(), 3);
+        for country in ["de", "fr", "us"] {
+            let users = by_country.query(&(country, ())).unwrap();
+            assert!((90..=110).contains(&users), "{}", users);
+        }
+        assert_eq!(by_country.query(&("nl", ())), None);
+    }
+
+    #[test]
+    fn group_by_keeps_heavy_keys() {
+        let mut a = GroupBy::<u32, ExactCounter>::with_max_keys(StreamProcessor::<()>::new(), 2);
+        let mut b = a.clone();
+        for i in 0..1000u32 {
+            let k = if i % 2 == 0 { 0 } else { i };
+            a.process((k, ()));
+            b.process((k + 1, ()));
+        }
+        assert!(a.len() <= 2);
+        // the heavy key misses at most n / 3 of its elements
+        let heavy = StreamProcessor::<(u32, ())>::query(&a, &(0, ()));
+        assert!(heavy.unwrap() >= 500 - 1000 / 3);
+        a.merge(b);
+        assert!(a.len() <= 2);
+        assert!(a.get(&0).is_some());
+        assert!(a.get(&1).is_some());
+    }
+
+    #[test]
+    fn median_of_morris() {
+        // A single Morris counter has a standard deviation of about 0.7n.
+        let count = MedianOfMeans::<MorrisCounter, 16, 9>::apply(0..10_000, &());
+        assert!(count.abs_diff(10_000) < 5_000, "{}", count);
+    }
+}
@@ -1,31 +1,44 @@
-use super::{Bitmap, StreamProcessor};
-use std::collections::hash_map::RandomState;
-use std::hash::{BuildHasher, Hash};
+use super::hash::XxHash64;
+use super::{seed, BitVec, Bitmap, HashFunction, Merge, StreamProcessor, HASH_BATCH};
+use std::array;
+use std::f64::consts::LN_2;
 use std::marker::PhantomData;
 
 /// Counts the approximate number of distinct elements in an iterator.
 /// Uses FM sketch streaming algorithm.
-pub struct BloomFilter<T, S = RandomState, const BYTES: usize = 16, const H: usize = 4> {
+pub struct BloomFilter<T, S = XxHash64, const BYTES: usize = 16, const H: usize = 4> {
     marker: PhantomData<T>,
     hashers: [S; H],
     bitmap: Bitmap<BYTES>,
 }
 
-impl<T: Hash, S: BuildHasher + Default, const N: usize> StreamProcessor<T>
-    for BloomFilter<T, S, N>
+impl<T, S: HashFunction<T>, const N: usize, const H: usize> StreamProcessor<T>
+    for BloomFilter<T, S, N, H>
 {
     fn new() -> Self {
         assert_ne!(N, 0);
         Self {
             marker: Default::default(),
-            hashers: Default::default(),
+            hashers: array::from_fn(|_| S::from_seed(seed())),
             bitmap: Bitmap::new(),
         }
     }
     fn process(&mut self, v: T) {
         assert_ne!(N, 0);
         for h in &self.hashers {
-            self.bitmap.set(h.hash_one(&v) as usize % Bitmap::<N>::BITS);
+            self.bitmap.set(h.hash(&v) as usize % Bitmap::<N>::BITS);
+        }
+    }
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        let hashers = self.hashers.iter().collect::<Vec<_>>();
+        let bitmap = &mut self.bitmap;
+        for vs in vs.chunks(HASH_BATCH) {
+            S::hash_many(&hashers, vs, |_, h| {
+                bitmap.set(h as usize % Bitmap::<N>::BITS)
+            });
         }
     }
 
@@ -35,6 +48,112 @@ impl<T: Hash, S: BuildHasher + Default, const N: usize> StreamProcessor<T>
         assert_ne!(N, 0);
         self.hashers
             .iter()
-            .all(|h| self.bitmap.get(h.hash_one(&t) as usize % Bitmap::<N>::BITS))
+            .all(|h| self.bitmap.get(h.hash(t) as usize % Bitmap::<N>::BITS))
+    }
+}
+
+impl<T, S, const N: usize, const H: usize> Merge for BloomFilter<T, S, N, H> {
+    fn merge(&mut self, other: Self) {
+        self.bitmap.union(&other.bitmap);
+    }
+}
+
+/// Bloom filter whose number of bits and hash functions are chosen at runtime.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DynBloomFilter<T, S = XxHash64> {
+    marker: PhantomData<T>,
+    hashers: Vec<S>,
+    bits: BitVec,
+}
+
+impl<T, S: HashFunction<T>> DynBloomFilter<T, S> {
+    /// Sizes the filter so that after inserting `expected_items` items, a query for an item which
+    /// was not inserted returns true with probability about `fp_rate`.
+    pub fn with_rate(expected_items: usize, fp_rate: f64) -> Self {
+        assert_ne!(expected_items, 0);
+        assert!(fp_rate > 0.0 && fp_rate < 1.0);
+        let n = expected_items as f64;
+        let bits = (-n * fp_rate.ln() / (LN_2 * LN_2)).ceil();
+        let hashes = (bits / n * LN_2).round().max(1.0);
+        Self::with_dimensions(bits as usize, hashes as usize)
+    }
+    /// Creates a filter with `bits` bits, setting `hashes` of them per item.
+    pub fn with_dimensions(bits: usize, hashes: usize) -> Self {
+        assert_ne!(hashes, 0);
+        Self {
+            marker: PhantomData,
+            hashers: (0..hashes).map(|_| S::from_seed(seed())).collect(),
+            bits: BitVec::new(bits),
+        }
+    }
+    pub fn num_bits(&self) -> usize {
+        self.bits.len()
+    }
+    pub fn num_hashes(&self) -> usize {
+        self.hashers.len()
+    }
+}
+
+impl<T, S: HashFunction<T>> StreamProcessor<T> for DynBloomFilter<T, S> {
+    /// Creates a filter for 10,000 items with a 1% false positive rate.
+    fn new() -> Self {
+        Self::with_rate(10_000, 0.01)
+    }
+    fn process(&mut self, v: T) {
+        let len = self.bits.len();
+        for h in &self.hashers {
+            self.bits.set(h.hash(&v) as usize % len);
+        }
+    }
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        let len = self.bits.len();
+        let hashers = self.hashers.iter().collect::<Vec<_>>();
+        let bits = &mut self.bits;
+        for vs in vs.chunks(HASH_BATCH) {
+            S::hash_many(&hashers, vs, |_, h| bits.set(h as usize % len));
+        }
+    }
+
+    type Result = bool;
+    type Args = T;
+    fn query(&self, t: &T) -> bool {
+        let len = self.bits.len();
+        self.hashers
+            .iter()
+            .all(|h| self.bits.get(h.hash(t) as usize % len))
+    }
+}
+
+impl<T, S> Merge for DynBloomFilter<T, S> {
+    fn merge(&mut self, other: Self) {
+        self.bits.union(&other.bits);
+    }
+}
+
+#[cfg(test)]
+mod test_bloom {
+    use super::DynBloomFilter;
+    use crate::StreamProcessor;
+
+    #[test]
+    fn sized_from_rate() {
+        let b = DynBloomFilter::<u32>::with_rate(1000, 0.01);
+        // m = -n ln(p) / ln(2)^2, k = m / n ln(2)
+        assert_eq!(b.num_bits(), 9586);
+        assert_eq!(b.num_hashes(), 7);
+    }
+
+    #[test]
+    fn no_false_negatives() {
+        let mut b = DynBloomFilter::<u32>::with_rate(1000, 0.01);
+        for i in 0..1000 {
+            b.process(i);
+        }
+        assert!((0..1000).all(|i| b.query(&i)));
+        let false_positives = (1000..11000).filter(|i| b.query(i)).count();
+        assert!(false_positives < 300, "{}", false_positives);
     }
 }
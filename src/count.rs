@@ -1,29 +1,97 @@
-use super::{rand, StreamProcessor};
+use super::rand::SplitMix64;
+use super::{seed, Estimate, Estimator, Merge, StreamProcessor};
 
 /// Keeps an approximate count of a very large stream
 /// Returning the total number of elements within a constant factor.
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub struct MorrisCounter<const ALPHA: f32> {
+/// The stored count is incremented with probability `(1 + alpha)^-count`, so a smaller alpha
+/// gives a more accurate count at the cost of a larger stored count.
+/// Each counter draws from its own generator, so separately created counters are independent.
+/// Clones also draw a fresh generator, so that clones of one counter handed to parallel workers
+/// do not make the same random choices, and merging them is like counting both streams at once.
+#[derive(Debug)]
+pub struct MorrisCounter {
     count: usize,
+    alpha: f32,
+    rng: SplitMix64,
+}
+
+impl MorrisCounter {
+    pub fn with_alpha(alpha: f32) -> Self {
+        assert!(alpha > 0.0);
+        Self {
+            count: 0,
+            alpha,
+            rng: SplitMix64::new(seed()),
+        }
+    }
+}
+
+impl Clone for MorrisCounter {
+    fn clone(&self) -> Self {
+        Self {
+            rng: SplitMix64::new(seed()),
+            ..*self
+        }
+    }
 }
 
-impl<T, const A: f32> StreamProcessor<T> for MorrisCounter<A> {
+impl PartialEq for MorrisCounter {
+    /// Counters are equal if they hold the same count, whatever their generators.
+    fn eq(&self, other: &Self) -> bool {
+        self.count == other.count && self.alpha == other.alpha
+    }
+}
+
+impl<T> StreamProcessor<T> for MorrisCounter {
+    /// Creates the classic Morris counter, with `alpha = 1`.
     fn new() -> Self {
-        Self { count: 0 }
+        Self::with_alpha(1.0)
     }
     fn process(&mut self, _: T) {
-        let r = rand();
-        if r < (1.0 + A).powi(self.count as i32).recip() {
+        let r = self.rng.next_f32();
+        if r < (1.0 + self.alpha).powi(self.count as i32).recip() {
             self.count += 1;
         }
     }
     type Result = usize;
+    type Args = ();
     fn query(&self, (): &()) -> usize {
-        let approx = ((1.0 + A).powi(self.count as i32) - 1.0) / A;
+        let approx = ((1.0 + self.alpha).powi(self.count as i32) - 1.0) / self.alpha;
         approx.round() as usize
     }
 }
 
+impl<T> Estimator<T> for MorrisCounter {
+    /// The estimate of `n` has variance `alpha * n * (n - 1) / 2`.
+    fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+        let n = StreamProcessor::<T>::query(self, &()) as f64;
+        let var = self.alpha as f64 * n * (n - 1.0).max(0.0) / 2.0;
+        Estimate::chebyshev(n, var.sqrt(), confidence)
+    }
+}
+
+impl Merge for MorrisCounter {
+    /// The smaller counter's i-th increment stands for `(1 + alpha)^i` elements, so each is
+    /// replayed into the larger counter with probability proportional to that weight, which
+    /// keeps the estimate unbiased.
+    fn merge(&mut self, mut other: Self) {
+        assert_eq!(
+            self.alpha, other.alpha,
+            "Can only merge counters with the same alpha"
+        );
+        if other.count > self.count {
+            std::mem::swap(self, &mut other);
+        }
+        let base = 1.0 + self.alpha;
+        for i in 0..other.count {
+            let p = base.powi(i as i32 - self.count as i32);
+            if self.rng.next_f32() < p {
+                self.count += 1;
+            }
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct ExactCounter {
     count: usize,
@@ -37,7 +105,20 @@ impl<T> StreamProcessor<T> for ExactCounter {
         self.count += 1;
     }
     type Result = usize;
+    type Args = ();
     fn query(&self, (): &()) -> usize {
         self.count
     }
 }
+
+impl<T> Estimator<T> for ExactCounter {
+    fn estimate(&self, (): &(), _confidence: f64) -> Estimate {
+        Estimate::exact(self.count as f64)
+    }
+}
+
+impl Merge for ExactCounter {
+    fn merge(&mut self, other: Self) {
+        self.count += other.count;
+    }
+}
@@ -0,0 +1,384 @@
+use super::estimate::check_confidence;
+use super::hash::CarterWegman;
+use super::high_freq::{count_min_bounds, count_min_dimensions};
+use super::{seed, Estimate, Estimator, HashFunction, StreamProcessor};
+use std::collections::HashMap;
+use std::f64::consts::LN_2;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Forward decay of Cormode, Shkapenyuk, Srivastava and Xu with `g(x) = exp(lambda * x)`.
+///
+/// An item at time `t` is stored with the weight `g(t - L)` for a landmark time `L`, and sums
+/// of weights are divided by `g(now - L)` when queried. That gives every item the weight
+/// `exp(-lambda * (now - t))`, so nothing has to be updated as time passes. The weights grow
+/// with time, so once they get large the landmark moves up to now, and stored sums are scaled
+/// down to match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ForwardDecay<const HALF_LIFE: u64> {
+    landmark: u64,
+    now: u64,
+}
+
+impl<const HALF_LIFE: u64> ForwardDecay<HALF_LIFE> {
+    /// Largest exponent of a weight before the landmark moves, far below where `f64`
+    /// overflows.
+    const MAX_EXPONENT: f64 = 256.0;
+    fn new() -> Self {
+        assert_ne!(HALF_LIFE, 0);
+        Self {
+            landmark: 0,
+            now: 0,
+        }
+    }
+    fn lambda() -> f64 {
+        LN_2 / HALF_LIFE as f64
+    }
+    /// Moves the clock to `t` if it is later than now. Returns the factor to multiply stored
+    /// sums by, which is not 1 if the landmark moved.
+    fn advance(&mut self, t: u64) -> f64 {
+        self.now = self.now.max(t);
+        let exponent = Self::lambda() * (self.now - self.landmark) as f64;
+        if exponent <= Self::MAX_EXPONENT {
+            return 1.0;
+        }
+        self.landmark = self.now;
+        (-exponent).exp()
+    }
+    /// Weight stored for an item at time `t`.
+    fn weight(&self, t: u64) -> f64 {
+        (Self::lambda() * (t as f64 - self.landmark as f64)).exp()
+    }
+    /// Divisor which turns stored sums into their decayed values at the current time.
+    fn norm(&self) -> f64 {
+        self.weight(self.now)
+    }
+}
+
+/// Counts elements of a stream of `(timestamp, value)` pairs, where each element's weight
+/// halves every `HALF_LIFE` time units after it arrives. Queries give the decayed count at the
+/// latest timestamp seen, or the latest passed to `tick`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecayedCounter<const HALF_LIFE: u64> {
+    decay: ForwardDecay<HALF_LIFE>,
+    sum: f64,
+}
+
+impl<const HALF_LIFE: u64> DecayedCounter<HALF_LIFE> {
+    /// Moves the clock to `t` without adding anything, so that queries decay over quiet
+    /// periods.
+    pub fn tick(&mut self, t: u64) {
+        self.sum *= self.decay.advance(t);
+    }
+}
+
+impl<T, const HALF_LIFE: u64> StreamProcessor<(u64, T)> for DecayedCounter<HALF_LIFE> {
+    fn new() -> Self {
+        Self {
+            decay: ForwardDecay::new(),
+            sum: 0.0,
+        }
+    }
+    fn process(&mut self, (t, _): (u64, T)) {
+        self.tick(t);
+        self.sum += self.decay.weight(t);
+    }
+    type Result = f64;
+    type Args = ();
+    fn query(&self, (): &()) -> f64 {
+        self.sum / self.decay.norm()
+    }
+}
+
+impl<T, const HALF_LIFE: u64> Estimator<(u64, T)> for DecayedCounter<HALF_LIFE> {
+    fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+        check_confidence(confidence);
+        Estimate::exact(StreamProcessor::<(u64, T)>::query(self, &()))
+    }
+}
+
+/// Count-Min sketch over a stream of `(timestamp, item)` pairs, which estimates the decayed
+/// count of an item, where each occurrence's weight halves every `HALF_LIFE` time units. Its
+/// width and depth are chosen at runtime, like `DynCountMin`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecayedCountMin<T, const HALF_LIFE: u64, S = CarterWegman> {
+    marker: PhantomData<T>,
+    decay: ForwardDecay<HALF_LIFE>,
+    width: usize,
+    hashers: Vec<S>,
+    /// Stored weights for each hash function, row after row.
+    counts: Vec<f64>,
+    total: f64,
+}
+
+impl<T, const HALF_LIFE: u64, S: HashFunction<T>> DecayedCountMin<T, HALF_LIFE, S> {
+    /// Sizes the sketch so that an estimate exceeds the true decayed count by at most `epsilon`
+    /// times the decayed length of the stream, with probability at least `1 - delta`.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        let (width, depth) = count_min_dimensions(epsilon, delta);
+        Self::with_dimensions(width, depth)
+    }
+    /// Creates a sketch with `depth` rows of `width` counters each.
+    pub fn with_dimensions(width: usize, depth: usize) -> Self {
+        assert_ne!(width, 0);
+        assert_ne!(depth, 0);
+        Self {
+            marker: PhantomData,
+            decay: ForwardDecay::new(),
+            width,
+            hashers: (0..depth).map(|_| S::from_seed(seed())).collect(),
+            counts: vec![0.0; width * depth],
+            total: 0.0,
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn depth(&self) -> usize {
+        self.hashers.len()
+    }
+    /// Moves the clock to `t` without adding anything, so that queries decay over quiet
+    /// periods.
+    pub fn tick(&mut self, t: u64) {
+        let scale = self.decay.advance(t);
+        if scale != 1.0 {
+            self.counts.iter_mut().for_each(|c| *c *= scale);
+            self.total *= scale;
+        }
+    }
+    /// Decayed length of the stream.
+    pub fn total(&self) -> f64 {
+        self.total / self.decay.norm()
+    }
+}
+
+impl<T, const HALF_LIFE: u64, S: HashFunction<T>> StreamProcessor<(u64, T)>
+    for DecayedCountMin<T, HALF_LIFE, S>
+{
+    /// Creates a sketch with `epsilon = 0.001` and `delta = 0.01`.
+    fn new() -> Self {
+        Self::with_error(0.001, 0.01)
+    }
+    fn process(&mut self, (t, v): (u64, T)) {
+        self.tick(t);
+        let weight = self.decay.weight(t);
+        let w = self.width;
+        for (s, row) in self.hashers.iter().zip(self.counts.chunks_exact_mut(w)) {
+            row[s.hash(&v) as usize % w] += weight;
+        }
+        self.total += weight;
+    }
+    type Result = f64;
+    type Args = T;
+    fn query(&self, v: &T) -> f64 {
+        let w = self.width;
+        let min = self
+            .hashers
+            .iter()
+            .zip(self.counts.chunks_exact(w))
+            .map(|(s, row)| row[s.hash(v) as usize % w])
+            .fold(f64::INFINITY, f64::min);
+        min / self.decay.norm()
+    }
+}
+
+impl<T, const HALF_LIFE: u64, S: HashFunction<T>> Estimator<(u64, T)>
+    for DecayedCountMin<T, HALF_LIFE, S>
+{
+    fn estimate(&self, v: &T, confidence: f64) -> Estimate {
+        count_min_bounds(
+            self.query(v),
+            self.total(),
+            self.width,
+            self.depth(),
+            confidence,
+        )
+    }
+}
+
+/// Finds the items with the largest decayed counts in a stream of `(timestamp, item)` pairs,
+/// where each occurrence's weight halves every `HALF_LIFE` time units.
+///
+/// Uses weighted Space-Saving with `K` counters: an untracked item replaces the one with the
+/// smallest count, and takes over its count as possible error. Any item whose decayed count is
+/// more than a `1 / K` fraction of the decayed length of the stream is tracked.
+#[derive(Clone, Debug)]
+pub struct DecayedHeavyHitters<T, const K: usize, const HALF_LIFE: u64> {
+    decay: ForwardDecay<HALF_LIFE>,
+    /// Stored weight of each tracked item, and how much of it may belong to other items.
+    pub counts: HashMap<T, (f64, f64)>,
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const HALF_LIFE: u64>
+    DecayedHeavyHitters<T, K, HALF_LIFE>
+{
+    /// Moves the clock to `t` without adding anything, so that queries decay over quiet
+    /// periods.
+    pub fn tick(&mut self, t: u64) {
+        let scale = self.decay.advance(t);
+        if scale != 1.0 {
+            for (count, err) in self.counts.values_mut() {
+                *count *= scale;
+                *err *= scale;
+            }
+        }
+    }
+    /// Returns up to `k` of the items with the largest decayed counts, with their estimated
+    /// counts, largest first.
+    pub fn top(&self, k: usize) -> Vec<(T, f64)> {
+        let norm = self.decay.norm();
+        let mut top = self
+            .counts
+            .iter()
+            .map(|(v, &(count, _))| (v.clone(), count / norm))
+            .collect::<Vec<_>>();
+        top.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        top.truncate(k);
+        top
+    }
+    /// Smallest stored weight, which bounds the weight of every untracked item.
+    fn min_count(&self) -> f64 {
+        if self.counts.len() < K {
+            return 0.0;
+        }
+        let min = self.counts.values().map(|&(count, _)| count);
+        min.fold(f64::INFINITY, f64::min)
+    }
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const HALF_LIFE: u64> StreamProcessor<(u64, T)>
+    for DecayedHeavyHitters<T, K, HALF_LIFE>
+{
+    fn new() -> Self {
+        assert_ne!(K, 0);
+        Self {
+            decay: ForwardDecay::new(),
+            counts: HashMap::with_capacity(K),
+        }
+    }
+    fn process(&mut self, (t, v): (u64, T)) {
+        self.tick(t);
+        let weight = self.decay.weight(t);
+        if let Some((count, _)) = self.counts.get_mut(&v) {
+            *count += weight;
+            return;
+        }
+        if self.counts.len() < K {
+            self.counts.insert(v, (weight, 0.0));
+            return;
+        }
+        let (min, _) = self
+            .counts
+            .iter()
+            .min_by(|a, b| a.1 .0.total_cmp(&b.1 .0))
+            .map(|(k, &c)| (k.clone(), c))
+            .unwrap();
+        let (count, _) = self.counts.remove(&min).unwrap();
+        self.counts.insert(v, (count + weight, count));
+    }
+    type Result = f64;
+    type Args = T;
+    /// Returns the estimated decayed count of an item, which never underestimates it. Untracked
+    /// items get the smallest tracked count, which bounds theirs.
+    fn query(&self, v: &T) -> f64 {
+        let count = match self.counts.get(v) {
+            Some(&(count, _)) => count,
+            None => self.min_count(),
+        };
+        count / self.decay.norm()
+    }
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const HALF_LIFE: u64> Estimator<(u64, T)>
+    for DecayedHeavyHitters<T, K, HALF_LIFE>
+{
+    /// The bounds hold with certainty.
+    fn estimate(&self, v: &T, confidence: f64) -> Estimate {
+        check_confidence(confidence);
+        let norm = self.decay.norm();
+        let (value, lower, upper) = match self.counts.get(v) {
+            Some(&(count, err)) => (count, count - err, count),
+            None => (self.min_count(), 0.0, self.min_count()),
+        };
+        Estimate {
+            value: value / norm,
+            lower: lower / norm,
+            upper: upper / norm,
+            confidence,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_decay {
+    use super::{DecayedCountMin, DecayedCounter, DecayedHeavyHitters};
+    use crate::{Estimator, StreamProcessor};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn counter_halves() {
+        let mut c: DecayedCounter<10> = StreamProcessor::<(u64, ())>::new();
+        c.process((0, ()));
+        c.process((10, ()));
+        assert!(close(StreamProcessor::<(u64, ())>::query(&c, &()), 1.5));
+        c.tick(30);
+        assert!(close(StreamProcessor::<(u64, ())>::query(&c, &()), 0.375));
+    }
+
+    #[test]
+    fn counter_moves_landmark() {
+        // one element per time unit converges to 1 / (1 - 2^(-1/H))
+        let mut c: DecayedCounter<1> = StreamProcessor::<(u64, ())>::new();
+        for t in 0..100_000 {
+            c.process((t, ()));
+        }
+        let v = StreamProcessor::<(u64, ())>::query(&c, &());
+        assert!(close(v, 2.0), "{}", v);
+    }
+
+    #[test]
+    fn count_min_estimate() {
+        let mut cm = DecayedCountMin::<u32, 100>::with_error(0.01, 0.01);
+        let mut exact = 0.0;
+        for t in 0..100_000 {
+            cm.process((t, (t % 10) as u32));
+            exact *= 0.5f64.powf(0.01);
+            if t % 10 == 3 {
+                exact += 1.0;
+            }
+        }
+        let e = cm.estimate(&3, 0.99);
+        assert!(e.contains(exact), "{:?} {}", e, exact);
+        assert!(close(cm.total(), 1.0 / (1.0 - 0.5f64.powf(0.01))));
+    }
+
+    #[test]
+    fn heavy_hitters_follow_recent() {
+        let mut hh = DecayedHeavyHitters::<u32, 4, 50>::new();
+        for t in 0..2000 {
+            let hot = if t < 1000 { 1 } else { 2 };
+            hh.process((t, if t % 2 == 0 { hot } else { t as u32 }));
+        }
+        assert_eq!(hh.top(1)[0].0, 2);
+        // 2 arrived at odd ages, decaying by 2^(-1/50) per step, and 1 at odd ages from 1001
+        let decayed = |ages: std::ops::Range<u32>| {
+            let ages = ages.step_by(2);
+            ages.map(|age| 0.5f64.powf(age as f64 / 50.0)).sum::<f64>()
+        };
+        // 1 was evicted long ago, so it gets the bound of every untracked item
+        assert!(!hh.counts.contains_key(&1));
+        assert!(hh.query(&1) >= decayed(1001..2000));
+        assert!(hh.query(&1) < hh.query(&2));
+        let e = hh.estimate(&2, 0.9);
+        let exact = decayed(1..1000);
+        assert!(
+            e.lower <= exact + 1e-9 && exact <= e.upper + 1e-9,
+            "{:?} {}",
+            e,
+            exact
+        );
+    }
+}
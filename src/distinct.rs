@@ -1,35 +1,52 @@
-use super::{Bitmap, StreamProcessor};
-use std::collections::hash_map::RandomState;
-use std::hash::{BuildHasher, Hash};
+use super::adapters::GroupBy;
+use super::estimate::check_confidence;
+use super::hash::XxHash64;
+use super::{seed, Bitmap, Estimate, Estimator, HashFunction, Merge, StreamProcessor, HASH_BATCH};
+use std::hash::Hash;
 use std::marker::PhantomData;
 
 /// Counts the approximate number of distinct elements in an iterator.
 /// Uses FM sketch streaming algorithm.
-pub struct FlajoletMartin<T, S = RandomState, const BYTES: usize = 8> {
+pub struct FlajoletMartin<T, S = XxHash64, const BYTES: usize = 8> {
     marker: PhantomData<T>,
     state: S,
     bitmap: Bitmap<BYTES>,
 }
 
 const PHI: f32 = 0.77351;
-impl<T: Hash, S: BuildHasher + Default, const N: usize> StreamProcessor<T>
-    for FlajoletMartin<T, S, N>
-{
+impl<T, S: HashFunction<T>, const N: usize> StreamProcessor<T> for FlajoletMartin<T, S, N> {
     fn new() -> Self {
         assert_ne!(N, 0);
         Self {
             marker: Default::default(),
-            state: Default::default(),
+            state: S::from_seed(seed()),
             bitmap: Bitmap::new(),
         }
     }
     fn process(&mut self, v: T) {
         assert_ne!(N, 0);
         self.bitmap
-            .set_or_max(self.state.hash_one(v).trailing_zeros() as usize)
+            .set_or_max(self.state.hash(&v).trailing_zeros() as usize)
+    }
+    /// Collects which bits the batch sets into one word before touching the bitmap.
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        assert_ne!(N, 0);
+        let mut seen = 0u128;
+        for vs in vs.chunks(HASH_BATCH) {
+            S::hash_many(&[&self.state], vs, |_, h| seen |= 1 << h.trailing_zeros());
+        }
+        for i in 0..128 {
+            if seen >> i & 1 == 1 {
+                self.bitmap.set_or_max(i);
+            }
+        }
     }
 
     type Result = usize;
+    type Args = ();
     fn query(&self, (): &()) -> usize {
         assert_ne!(N, 0);
         let approx = |n: usize| (2f32.powi(n as i32) / PHI) as usize - 1;
@@ -46,25 +63,318 @@ impl<T: Hash, S: BuildHasher + Default, const N: usize> StreamProcessor<T>
     }
 }
 
+impl<T, S: HashFunction<T>, const N: usize> Estimator<T> for FlajoletMartin<T, S, N> {
+    /// The position of the lowest unset bit has a standard deviation of about 1.12, so the
+    /// estimate is bounded within a power of two of that many standard deviations.
+    fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+        check_confidence(confidence);
+        let value = self.query(&()) as f64;
+        let factor = 2f64.powf(1.12 / (1.0 - confidence).sqrt());
+        Estimate {
+            value,
+            lower: value / factor,
+            upper: (value + 1.0) * factor,
+            confidence,
+        }
+    }
+}
+
+impl<T, S, const N: usize> Merge for FlajoletMartin<T, S, N> {
+    fn merge(&mut self, other: Self) {
+        self.bitmap.union(&other.bitmap);
+    }
+}
+
+/// Counts the approximate number of distinct elements in an iterator.
+/// Uses the HyperLogLog sketch, with `2^P` registers of one byte each.
+/// The relative standard error is about `1.04 / sqrt(2^P)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog<T, S = XxHash64, const P: usize = 12> {
+    marker: PhantomData<T>,
+    state: S,
+    registers: Vec<u8>,
+}
+
+impl<T, S, const P: usize> HyperLogLog<T, S, P> {
+    const M: usize = 1 << P;
+    fn alpha() -> f64 {
+        match Self::M {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            m => 0.7213 / (1.0 + 1.079 / m as f64),
+        }
+    }
+    /// Relative standard error of the estimate.
+    pub fn std_error() -> f64 {
+        1.04 / (Self::M as f64).sqrt()
+    }
+}
+
+impl<T, S: HashFunction<T>, const P: usize> HyperLogLog<T, S, P> {
+    /// Adds `v`, returning whether it raised a register, which repeats of an element never do.
+    pub(crate) fn insert(&mut self, v: &T) -> bool {
+        // The low P bits pick a register, and the rest of the hash its rank.
+        let h = self.state.hash(v);
+        let idx = h as usize & (Self::M - 1);
+        let rest_bits = S::BITS - P as u32;
+        let rank = (h >> P).trailing_zeros().min(rest_bits) as u8 + 1;
+        let reg = &mut self.registers[idx];
+        let raised = rank > *reg;
+        *reg = (*reg).max(rank);
+        raised
+    }
+}
+
+impl<T, S: HashFunction<T>, const P: usize> StreamProcessor<T> for HyperLogLog<T, S, P> {
+    fn new() -> Self {
+        assert!((4..=18).contains(&P), "HyperLogLog needs 4 <= P <= 18");
+        assert!(S::BITS as usize > P);
+        Self {
+            marker: PhantomData,
+            state: S::from_seed(seed()),
+            registers: vec![0; Self::M],
+        }
+    }
+    fn process(&mut self, v: T) {
+        self.insert(&v);
+    }
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        let rest_bits = S::BITS - P as u32;
+        let registers = &mut self.registers;
+        for vs in vs.chunks(HASH_BATCH) {
+            S::hash_many(&[&self.state], vs, |_, h| {
+                let idx = h as usize & (Self::M - 1);
+                let rank = (h >> P).trailing_zeros().min(rest_bits) as u8 + 1;
+                registers[idx] = registers[idx].max(rank);
+            });
+        }
+    }
+
+    type Result = usize;
+    type Args = ();
+    fn query(&self, (): &()) -> usize {
+        let m = Self::M as f64;
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = Self::alpha() * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        let approx = if raw <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate for small cardinalities.
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        approx.round() as usize
+    }
+}
+
+impl<T, S: HashFunction<T>, const P: usize> Estimator<T> for HyperLogLog<T, S, P> {
+    fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+        let value = self.query(&()) as f64;
+        // Small counts are off by whole elements when two of them share a register, so the
+        // standard deviation is at least one rather than a fraction of one.
+        let std_dev = (value * Self::std_error()).max(1.0);
+        Estimate::chebyshev(value, std_dev, confidence)
+    }
+}
+
+impl<T, S, const P: usize> Merge for HyperLogLog<T, S, P> {
+    fn merge(&mut self, other: Self) {
+        for (a, b) in self.registers.iter_mut().zip(other.registers) {
+            *a = (*a).max(b);
+        }
+    }
+}
+
+/// Finds the keys of a stream of `(key, value)` pairs which appear with many distinct values,
+/// such as sources contacting many destinations.
+///
+/// Distinct pairs are sampled by their hash, so that every repeat of a pair is kept or dropped
+/// together, and the sampled pairs are counted by a `HyperLogLog` with `2^P` registers per key.
+/// The keys are capped by a `GroupBy::with_max_keys`, so memory stays bounded, and a key with
+/// `D` distinct values keeps about `rate * D` samples. Both sampling and the sketches add noise,
+/// so thresholds should be large enough for `rate * D` to be at least several dozen.
+///
+/// The cap only counts pairs which are new, so that repeats cannot evict a key with many
+/// distinct values: a tracked key gains weight when its pair raises a register of its sketch,
+/// and an untracked key is admitted, or charged against the others, when its pair raises a
+/// register of one more `HyperLogLog` over the pairs of untracked keys. Weights thus grow with
+/// the number of distinct values, though only about logarithmically beyond `2^P` of them.
+#[derive(Clone, Debug)]
+pub struct Superspreaders<K, V, S = XxHash64, const P: usize = 8> {
+    sampler: S,
+    /// Hashes below `rate * 2^BITS` are sampled.
+    limit: f64,
+    rate: f64,
+    groups: GroupBy<K, HyperLogLog<V, S, P>>,
+    /// Sampled pairs whose key had no group, so that their repeats are not charged again.
+    untracked: HyperLogLog<(K, V), S, P>,
+}
+
+impl<K, V, S: HashFunction<(K, V)> + HashFunction<V>, const P: usize> Superspreaders<K, V, S, P> {
+    /// Samples a `rate` fraction of the distinct pairs, and keeps a sketch for at most
+    /// `max_keys` keys.
+    pub fn with_sampling(rate: f64, max_keys: usize) -> Self {
+        assert!(rate > 0.0 && rate <= 1.0);
+        let bits = <S as HashFunction<(K, V)>>::BITS;
+        Self {
+            sampler: HashFunction::<(K, V)>::from_seed(seed()),
+            limit: rate * 2f64.powi(bits as i32),
+            rate,
+            groups: GroupBy::with_max_keys(HyperLogLog::new(), max_keys),
+            untracked: HyperLogLog::new(),
+        }
+    }
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+    /// Estimates how many distinct values a key has, if it is tracked.
+    pub fn distinct(&self, k: &K) -> Option<usize>
+    where
+        K: Hash + Eq,
+    {
+        let sampled = self.groups.get(k)?.query(&());
+        Some((sampled as f64 / self.rate).round() as usize)
+    }
+}
+
+impl<K, V, S, const P: usize> StreamProcessor<(K, V)> for Superspreaders<K, V, S, P>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: HashFunction<(K, V)> + HashFunction<V> + Clone,
+{
+    /// Samples a tenth of the pairs, for at most 1024 keys.
+    fn new() -> Self {
+        Self::with_sampling(0.1, 1024)
+    }
+    fn process(&mut self, pair: (K, V)) {
+        if (self.sampler.hash(&pair) as f64) >= self.limit {
+            return;
+        }
+        let untracked = &mut self.untracked;
+        self.groups.process_weighted(
+            pair.0.clone(),
+            |hll| hll.insert(&pair.1),
+            || untracked.insert(&pair),
+        );
+    }
+    /// Keys with an estimate of at least that many distinct values, with their estimates,
+    /// largest first.
+    type Result = Vec<(K, usize)>;
+    type Args = usize;
+    fn query(&self, &threshold: &usize) -> Vec<(K, usize)> {
+        let mut spreaders = self
+            .groups
+            .groups()
+            .map(|(k, hll)| {
+                (
+                    k.clone(),
+                    (hll.query(&()) as f64 / self.rate).round() as usize,
+                )
+            })
+            .filter(|&(_, d)| d >= threshold)
+            .collect::<Vec<_>>();
+        spreaders.sort_unstable_by_key(|&(_, d)| std::cmp::Reverse(d));
+        spreaders
+    }
+}
+
+impl<K: Hash + Eq, V, S, const P: usize> Merge for Superspreaders<K, V, S, P> {
+    /// Both must be clones of one `Superspreaders`, so that they sample the same pairs.
+    fn merge(&mut self, other: Self) {
+        assert_eq!(self.limit, other.limit);
+        self.groups.merge(other.groups);
+        self.untracked.merge(other.untracked);
+    }
+}
+
 #[cfg(test)]
 mod test_distinct {
-    use crate::StreamProcessor;
-    use std::collections::hash_map::RandomState;
+    use super::Superspreaders;
+    use crate::hash::XxHash64;
+    use crate::{Estimator, Merge, StreamProcessor};
     #[test]
     fn empty() {
         assert_eq!(
             0,
-            super::FlajoletMartin::<&u32, RandomState, 8>::apply(vec![].into_iter(), &())
+            super::FlajoletMartin::<&u32, XxHash64, 8>::apply(vec![].into_iter(), &())
         )
     }
+    #[test]
+    fn hll() {
+        for n in [10, 1000, 100_000] {
+            let hll = super::HyperLogLog::<u32>::apply(0..n, &());
+            // 4 standard errors, which for n = 10 is under one element, so one register
+            // collision (about 1% of sketches) is allowed too
+            assert!(
+                hll.abs_diff(n as usize) as f64 <= (0.07 * n as f64).max(1.0),
+                "{} vs {}",
+                hll,
+                n
+            );
+        }
+    }
     // probabilistic
     quickcheck! {
       fn fm(x: Vec<u32>) -> bool {
-        let distinct_count = super::FlajoletMartin::<_, RandomState, 8>::apply(x.iter(), &());
+        let distinct_count = super::FlajoletMartin::<_, XxHash64, 8>::apply(x.iter(), &());
         let mut x = x.clone();
         x.sort_unstable();
         x.dedup();
         x.len().abs_diff(distinct_count) < 1000+x.len()/2
       }
+      fn hll_estimate(x: Vec<u16>) -> bool {
+        let mut hll = super::HyperLogLog::<_, XxHash64, 10>::new();
+        for v in &x {
+            hll.process(v);
+        }
+        let mut x = x.clone();
+        x.sort_unstable();
+        x.dedup();
+        hll.estimate(&(), 0.99).contains(x.len() as f64)
+      }
+    }
+
+    #[test]
+    fn superspreaders() {
+        // 5 sources each contact 2000 destinations, and 1000 others contact 5 destinations 4
+        // times each.
+        let spread = (0..2000u32).flat_map(|d| (0..5u32).map(move |s| (s, d)));
+        let chatty = (0..20_000u32).map(|i| (5 + i % 1000, i / 1000 % 5));
+        let pairs = spread
+            .zip(chatty)
+            .flat_map(|(a, b)| [a, b])
+            .collect::<Vec<_>>();
+        let (left, right) = pairs.split_at(7000);
+        let mut ss = Superspreaders::<u32, u32>::with_sampling(0.25, 256);
+        let mut other = ss.clone();
+        left.iter().for_each(|&p| ss.process(p));
+        right.iter().for_each(|&p| other.process(p));
+        ss.merge(other);
+        let mut found = ss.query(&1000);
+        assert!(
+            found.iter().all(|&(_, d)| d.abs_diff(2000) < 600),
+            "{:?}",
+            found
+        );
+        found.sort_unstable();
+        let sources = found.iter().map(|&(s, _)| s).collect::<Vec<_>>();
+        assert_eq!(sources, [0, 1, 2, 3, 4]);
+        assert_eq!(ss.distinct(&1_000_000), None);
+    }
+
+    #[test]
+    fn superspreaders_survive_repeats() {
+        // Source 0 contacts 100 destinations once each, while 1 and 2 repeat one pair each.
+        let pairs = (0..100u32).flat_map(|d| vec![(0, d), (1, 0), (1, 0), (2, 0), (2, 0)]);
+        let mut ss = Superspreaders::<u32, u32>::with_sampling(1.0, 2);
+        pairs.for_each(|p| ss.process(p));
+        let found = ss.query(&50);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!(found[0].0, 0);
     }
 }
@@ -0,0 +1,100 @@
+use super::StreamProcessor;
+
+/// An approximate result, along with an interval which contains the true value with
+/// probability at least `confidence`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub confidence: f64,
+}
+
+impl Estimate {
+    /// An estimate which is known to be the true value.
+    pub fn exact(value: f64) -> Self {
+        Self {
+            value,
+            lower: value,
+            upper: value,
+            confidence: 1.0,
+        }
+    }
+    /// Bounds a non-negative estimate with known standard deviation using Chebyshev's
+    /// inequality, which holds for any distribution of the estimate.
+    pub(crate) fn chebyshev(value: f64, std_dev: f64, confidence: f64) -> Self {
+        check_confidence(confidence);
+        let t = std_dev / (1.0 - confidence).sqrt();
+        Self {
+            value,
+            lower: (value - t).max(0.0),
+            upper: value + t,
+            confidence,
+        }
+    }
+    /// Bounds a non-negative median of `groups` independent means, each with standard deviation
+    /// `std_dev`. By Chebyshev's inequality a mean is `t` standard deviations off with
+    /// probability at most `q = 1 / t^2`, and the median is only that far off if half of the
+    /// means are, so `q` is the largest for which that has probability at most `1 - confidence`.
+    pub(crate) fn median_of_means(
+        value: f64,
+        std_dev: f64,
+        groups: usize,
+        confidence: f64,
+    ) -> Self {
+        check_confidence(confidence);
+        assert_ne!(groups, 0);
+        let half = groups.div_ceil(2);
+        // probability that at least half of the means are off
+        let tail = |q: f64| {
+            let mut term = (1.0 - q).powi(groups as i32);
+            let mut sum = 0.0;
+            for k in 0..=groups {
+                if k >= half {
+                    sum += term;
+                }
+                term *= (groups - k) as f64 / (k + 1) as f64 * q / (1.0 - q);
+            }
+            sum
+        };
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..64 {
+            let mid = (lo + hi) / 2.0;
+            if tail(mid) <= 1.0 - confidence {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let t = std_dev / f64::sqrt(lo);
+        Self {
+            value,
+            lower: (value - t).max(0.0),
+            upper: value + t,
+            confidence,
+        }
+    }
+    /// Whether the interval contains `v`.
+    pub fn contains(&self, v: f64) -> bool {
+        self.lower <= v && v <= self.upper
+    }
+    /// Width of the interval.
+    pub fn width(&self) -> f64 {
+        self.upper - self.lower
+    }
+}
+
+pub(crate) fn check_confidence(confidence: f64) {
+    assert!(
+        confidence > 0.0 && confidence < 1.0,
+        "Confidence must be in (0, 1), got {}",
+        confidence
+    );
+}
+
+/// A stream processor whose error analysis bounds the results of its queries.
+pub trait Estimator<T>: StreamProcessor<T> {
+    /// Returns the result of `query` with an interval which holds the true value with
+    /// probability at least `confidence`, where `0 < confidence < 1`.
+    fn estimate(&self, args: &Self::Args, confidence: f64) -> Estimate;
+}
@@ -0,0 +1,509 @@
+use super::rand::SplitMix64;
+use super::HashFunction;
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+/// The Mersenne prime 2^61 - 1, which polynomial hashes are evaluated modulo.
+const P61: u64 = (1 << 61) - 1;
+
+/// Reduces a value below 2^64 modulo 2^61 - 1.
+#[inline]
+fn reduce(v: u64) -> u64 {
+    let v = (v & P61) + (v >> 61);
+    if v >= P61 {
+        v - P61
+    } else {
+        v
+    }
+}
+
+/// Multiplies two values below 2^61 modulo 2^61 - 1.
+#[inline]
+fn mul_mod(a: u64, b: u64) -> u64 {
+    let prod = a as u128 * b as u128;
+    reduce((prod as u64 & P61) + (prod >> 61) as u64)
+}
+
+/// Maps an arbitrary hashable value to a 64 bit key, which the integer families then hash.
+/// Independence guarantees hold over distinct keys, which collide with negligible probability.
+#[inline]
+fn fingerprint<T: Hash + ?Sized>(v: &T) -> u64 {
+    XxHash64::new(0).hash_one(v)
+}
+
+/// Fingerprints each value once, then hashes the fingerprints with every member.
+#[inline]
+fn hash_fingerprints<T: Hash, H>(
+    hashers: &[&H],
+    vs: &[T],
+    mut f: impl FnMut(usize, u64),
+    hash_u64: impl Fn(&H, u64) -> u64,
+) {
+    let keys = vs.iter().map(fingerprint).collect::<Vec<_>>();
+    for (i, h) in hashers.iter().enumerate() {
+        for &k in &keys {
+            f(i, hash_u64(h, k));
+        }
+    }
+}
+
+/// Random polynomial of degree K - 1 over the integers modulo 2^61 - 1.
+/// Drawing one at random gives a K-wise independent hash function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolynomialHash<const K: usize> {
+    coeffs: [u64; K],
+}
+
+/// Pairwise independent hash family of Carter and Wegman, `(a * x + b) mod p`.
+pub type CarterWegman = PolynomialHash<2>;
+
+impl<const K: usize> PolynomialHash<K> {
+    /// Evaluates the polynomial at `x`, returning a value below 2^61 - 1.
+    #[inline]
+    pub fn hash_u64(&self, x: u64) -> u64 {
+        let x = reduce(x);
+        self.coeffs
+            .iter()
+            .fold(0, |acc, &c| reduce(mul_mod(acc, x) + c))
+    }
+}
+
+impl<T: Hash + ?Sized, const K: usize> HashFunction<T> for PolynomialHash<K> {
+    const BITS: u32 = 61;
+    fn from_seed(seed: u64) -> Self {
+        assert_ne!(K, 0);
+        let mut rng = SplitMix64::new(seed);
+        let coeffs = std::array::from_fn(|_| reduce(rng.next_u64()));
+        Self { coeffs }
+    }
+    #[inline]
+    fn hash(&self, v: &T) -> u64 {
+        self.hash_u64(fingerprint(v))
+    }
+    fn hash_many(hashers: &[&Self], vs: &[T], f: impl FnMut(usize, u64))
+    where
+        T: Sized,
+    {
+        hash_fingerprints(hashers, vs, f, Self::hash_u64)
+    }
+}
+
+/// Simple tabulation hashing, which XORs together random table entries for each byte of the
+/// key. It is 3-wise independent, and behaves like a fully random function for many sketches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tabulation {
+    tables: Box<[[u64; 256]; 8]>,
+}
+
+impl Tabulation {
+    #[inline]
+    pub fn hash_u64(&self, x: u64) -> u64 {
+        x.to_le_bytes()
+            .iter()
+            .zip(self.tables.iter())
+            .fold(0, |acc, (&b, table)| acc ^ table[b as usize])
+    }
+}
+
+impl<T: Hash + ?Sized> HashFunction<T> for Tabulation {
+    fn from_seed(seed: u64) -> Self {
+        let mut rng = SplitMix64::new(seed);
+        let mut tables = Box::new([[0; 256]; 8]);
+        for entry in tables.iter_mut().flat_map(|t| t.iter_mut()) {
+            *entry = rng.next_u64();
+        }
+        Self { tables }
+    }
+    #[inline]
+    fn hash(&self, v: &T) -> u64 {
+        self.hash_u64(fingerprint(v))
+    }
+    fn hash_many(hashers: &[&Self], vs: &[T], f: impl FnMut(usize, u64))
+    where
+        T: Sized,
+    {
+        hash_fingerprints(hashers, vs, f, Self::hash_u64)
+    }
+}
+
+/// MurmurHash3 (x64, 128 bit variant), keeping the low 64 bits of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Murmur3 {
+    seed: u64,
+}
+
+impl Murmur3 {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl BuildHasher for Murmur3 {
+    type Hasher = Murmur3Hasher;
+    fn build_hasher(&self) -> Murmur3Hasher {
+        Murmur3Hasher {
+            h1: self.seed,
+            h2: self.seed,
+            tail: [0; 16],
+            tail_len: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T: Hash + ?Sized> HashFunction<T> for Murmur3 {
+    fn from_seed(seed: u64) -> Self {
+        Self::new(seed)
+    }
+    #[inline]
+    fn hash(&self, v: &T) -> u64 {
+        self.hash_one(v)
+    }
+}
+
+const M3_C1: u64 = 0x87c3_7b91_1142_53d5;
+const M3_C2: u64 = 0x4cf5_ad43_2745_937f;
+
+fn m3_mix_k1(k1: u64) -> u64 {
+    k1.wrapping_mul(M3_C1).rotate_left(31).wrapping_mul(M3_C2)
+}
+
+fn m3_mix_k2(k2: u64) -> u64 {
+    k2.wrapping_mul(M3_C2).rotate_left(33).wrapping_mul(M3_C1)
+}
+
+fn m3_fmix(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^ (k >> 33)
+}
+
+/// Streaming state of [`Murmur3`].
+#[derive(Debug, Clone)]
+pub struct Murmur3Hasher {
+    h1: u64,
+    h2: u64,
+    tail: [u8; 16],
+    tail_len: usize,
+    len: u64,
+}
+
+impl Murmur3Hasher {
+    fn block(&mut self, block: &[u8]) {
+        let k1 = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let k2 = u64::from_le_bytes(block[8..16].try_into().unwrap());
+
+        self.h1 ^= m3_mix_k1(k1);
+        self.h1 = self
+            .h1
+            .rotate_left(27)
+            .wrapping_add(self.h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        self.h2 ^= m3_mix_k2(k2);
+        self.h2 = self
+            .h2
+            .rotate_left(31)
+            .wrapping_add(self.h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+}
+
+impl Hasher for Murmur3Hasher {
+    #[inline]
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if self.tail_len > 0 {
+            let n = (16 - self.tail_len).min(bytes.len());
+            self.tail[self.tail_len..self.tail_len + n].copy_from_slice(&bytes[..n]);
+            self.tail_len += n;
+            bytes = &bytes[n..];
+            if self.tail_len < 16 {
+                return;
+            }
+            let tail = self.tail;
+            self.block(&tail);
+            self.tail_len = 0;
+        }
+        let mut blocks = bytes.chunks_exact(16);
+        for block in &mut blocks {
+            self.block(block);
+        }
+        let rest = blocks.remainder();
+        self.tail[..rest.len()].copy_from_slice(rest);
+        self.tail_len = rest.len();
+    }
+    #[inline]
+    fn finish(&self) -> u64 {
+        let (mut h1, mut h2) = (self.h1, self.h2);
+        let mut tail = [0; 16];
+        tail[..self.tail_len].copy_from_slice(&self.tail[..self.tail_len]);
+        if self.tail_len > 8 {
+            h2 ^= m3_mix_k2(u64::from_le_bytes(tail[8..].try_into().unwrap()));
+        }
+        if self.tail_len > 0 {
+            h1 ^= m3_mix_k1(u64::from_le_bytes(tail[..8].try_into().unwrap()));
+        }
+
+        h1 ^= self.len;
+        h2 ^= self.len;
+        h1 = h1.wrapping_add(h2);
+        h2 = h2.wrapping_add(h1);
+        h1 = m3_fmix(h1);
+        h2 = m3_fmix(h2);
+        h1.wrapping_add(h2)
+    }
+}
+
+/// xxHash, 64 bit variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct XxHash64 {
+    seed: u64,
+}
+
+impl XxHash64 {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl BuildHasher for XxHash64 {
+    type Hasher = XxHash64Hasher;
+    fn build_hasher(&self) -> XxHash64Hasher {
+        let seed = self.seed;
+        XxHash64Hasher {
+            seed,
+            acc: [
+                seed.wrapping_add(XX_P1).wrapping_add(XX_P2),
+                seed.wrapping_add(XX_P2),
+                seed,
+                seed.wrapping_sub(XX_P1),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T: Hash + ?Sized> HashFunction<T> for XxHash64 {
+    fn from_seed(seed: u64) -> Self {
+        Self::new(seed)
+    }
+    #[inline]
+    fn hash(&self, v: &T) -> u64 {
+        self.hash_one(v)
+    }
+}
+
+const XX_P1: u64 = 0x9e37_79b1_85eb_ca87;
+const XX_P2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const XX_P3: u64 = 0x1656_67b1_9e37_79f9;
+const XX_P4: u64 = 0x85eb_ca77_c2b2_ae63;
+const XX_P5: u64 = 0x27d4_eb2f_1656_67c5;
+
+fn xx_round(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(XX_P2))
+        .rotate_left(31)
+        .wrapping_mul(XX_P1)
+}
+
+fn xx_merge(acc: u64, val: u64) -> u64 {
+    (acc ^ xx_round(0, val))
+        .wrapping_mul(XX_P1)
+        .wrapping_add(XX_P4)
+}
+
+/// Streaming state of [`XxHash64`].
+#[derive(Debug, Clone)]
+pub struct XxHash64Hasher {
+    seed: u64,
+    acc: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    len: u64,
+}
+
+impl XxHash64Hasher {
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (acc, lane) in self.acc.iter_mut().zip(stripe.chunks_exact(8)) {
+            *acc = xx_round(*acc, u64::from_le_bytes(lane.try_into().unwrap()));
+        }
+    }
+}
+
+impl Hasher for XxHash64Hasher {
+    #[inline]
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if self.buf_len > 0 {
+            let n = (32 - self.buf_len).min(bytes.len());
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&bytes[..n]);
+            self.buf_len += n;
+            bytes = &bytes[n..];
+            if self.buf_len < 32 {
+                return;
+            }
+            let buf = self.buf;
+            self.stripe(&buf);
+            self.buf_len = 0;
+        }
+        let mut stripes = bytes.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+    #[inline]
+    fn finish(&self) -> u64 {
+        let mut h = if self.len >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let h = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            self.acc.iter().fold(h, |h, &v| xx_merge(h, v))
+        } else {
+            self.seed.wrapping_add(XX_P5)
+        };
+        h = h.wrapping_add(self.len);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            let lane = u64::from_le_bytes(rest[..8].try_into().unwrap());
+            h ^= xx_round(0, lane);
+            h = h.rotate_left(27).wrapping_mul(XX_P1).wrapping_add(XX_P4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let lane = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            h ^= lane.wrapping_mul(XX_P1);
+            h = h.rotate_left(23).wrapping_mul(XX_P2).wrapping_add(XX_P3);
+            rest = &rest[4..];
+        }
+        for &b in rest {
+            h ^= (b as u64).wrapping_mul(XX_P5);
+            h = h.rotate_left(11).wrapping_mul(XX_P1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(XX_P2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(XX_P3);
+        h ^ (h >> 32)
+    }
+}
+
+#[cfg(test)]
+mod test_hash {
+    use super::*;
+
+    const INPUTS: [&str; 6] = [
+        "",
+        "a",
+        "abc",
+        "hello",
+        "The quick brown fox jumps over the lazy dog",
+        "0123456789abcdef0123456789abcdef0123456789",
+    ];
+
+    fn hash_bytes<S: BuildHasher>(s: &S, bytes: &[u8]) -> u64 {
+        let mut h = s.build_hasher();
+        h.write(bytes);
+        h.finish()
+    }
+
+    #[test]
+    fn xxhash64_reference() {
+        let expected = [
+            0xef46db3751d8e999,
+            0xd24ec4f1a98c6e5b,
+            0x44bc2cf5ad770999,
+            0x26c7827d889f6da3,
+            0x0b242d361fda71bc,
+            0xa76190c3acf08a1c,
+        ];
+        for (input, &exp) in INPUTS.iter().zip(expected.iter()) {
+            assert_eq!(hash_bytes(&XxHash64::new(0), input.as_bytes()), exp);
+        }
+        assert_eq!(
+            hash_bytes(&XxHash64::new(42), INPUTS[5].as_bytes()),
+            0xec3fafab8cf27c18
+        );
+    }
+
+    #[test]
+    fn murmur3_reference() {
+        let expected = [
+            0x0000000000000000,
+            0x85555565f6597889,
+            0xb4963f3f3fad7867,
+            0xcbd8a7b341bd9b02,
+            0xe34bbc7bbc071b6c,
+            0xb373b994805cb22f,
+        ];
+        for (input, &exp) in INPUTS.iter().zip(expected.iter()) {
+            assert_eq!(hash_bytes(&Murmur3::new(0), input.as_bytes()), exp);
+        }
+        assert_eq!(
+            hash_bytes(&Murmur3::new(42), INPUTS[5].as_bytes()),
+            0x0e62749b3127faaa
+        );
+    }
+
+    #[test]
+    fn split_writes() {
+        let bytes = INPUTS[5].as_bytes();
+        for split in 0..bytes.len() {
+            let mut xx = XxHash64::new(7).build_hasher();
+            let mut m3 = Murmur3::new(7).build_hasher();
+            for h in [&mut xx as &mut dyn Hasher, &mut m3] {
+                h.write(&bytes[..split]);
+                h.write(&bytes[split..]);
+            }
+            assert_eq!(xx.finish(), hash_bytes(&XxHash64::new(7), bytes));
+            assert_eq!(m3.finish(), hash_bytes(&Murmur3::new(7), bytes));
+        }
+    }
+
+    #[test]
+    fn polynomial_in_field() {
+        let h = <CarterWegman as HashFunction<u64>>::from_seed(3);
+        for x in [0, 1, P61 - 1, P61, u64::MAX] {
+            assert!(h.hash_u64(x) < P61);
+        }
+        assert_eq!(h.hash_u64(5), h.hash_u64(P61 + 5));
+    }
+
+    #[test]
+    fn hash_many_matches_hash() {
+        let vs = [1u32, 5, 1 << 20, u32::MAX];
+        let cw = (0..3)
+            .map(<CarterWegman as HashFunction<u32>>::from_seed)
+            .collect::<Vec<_>>();
+        let cw_refs = cw.iter().collect::<Vec<_>>();
+        let mut out = vec![];
+        CarterWegman::hash_many(&cw_refs, &vs, |i, h| out.push((i, h)));
+        let expected = cw
+            .iter()
+            .enumerate()
+            .flat_map(|(i, h)| vs.iter().map(move |v| (i, h.hash(v))))
+            .collect::<Vec<_>>();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn seeds_differ() {
+        let a = <Tabulation as HashFunction<u32>>::from_seed(1);
+        let b = <Tabulation as HashFunction<u32>>::from_seed(2);
+        assert_ne!(a.hash(&10u32), b.hash(&10u32));
+        assert_eq!(a.hash(&10u32), a.clone().hash(&10u32));
+    }
+}
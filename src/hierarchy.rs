@@ -0,0 +1,185 @@
+use super::high_freq::MisraGries;
+use super::{Merge, StreamProcessor};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Generalization hierarchy over items, such as the prefixes of an address. Level 0 is the item
+/// itself, and each level generalizes the one below, so every prefix has a single parent.
+pub trait Hierarchy<T> {
+    const LEVELS: usize;
+    /// Generalizes `v`, which is at most at `level`, to that level.
+    fn generalize(v: &T, level: usize) -> T;
+}
+
+/// IPv4 addresses and their /24, /16 and /8 prefixes, kept as the first address of the prefix.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Ipv4Prefixes;
+
+impl Ipv4Prefixes {
+    pub const PREFIX_LENS: [u32; 4] = [32, 24, 16, 8];
+}
+
+impl Hierarchy<Ipv4Addr> for Ipv4Prefixes {
+    const LEVELS: usize = Self::PREFIX_LENS.len();
+    fn generalize(v: &Ipv4Addr, level: usize) -> Ipv4Addr {
+        let host_bits = 32 - Self::PREFIX_LENS[level];
+        Ipv4Addr::from(u32::from(*v) >> host_bits << host_bits)
+    }
+}
+
+/// IPv6 addresses and their /64, /48 and /32 prefixes, the usual subnet, site and provider
+/// allocations.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Ipv6Prefixes;
+
+impl Ipv6Prefixes {
+    pub const PREFIX_LENS: [u32; 4] = [128, 64, 48, 32];
+}
+
+impl Hierarchy<Ipv6Addr> for Ipv6Prefixes {
+    const LEVELS: usize = Self::PREFIX_LENS.len();
+    fn generalize(v: &Ipv6Addr, level: usize) -> Ipv6Addr {
+        let host_bits = 128 - Self::PREFIX_LENS[level];
+        Ipv6Addr::from(u128::from(*v) >> host_bits << host_bits)
+    }
+}
+
+/// Finds the hierarchical heavy hitters of a stream: the prefixes which are frequent once the
+/// counts of their heavy descendants are discounted, so that a heavy /24 is only reported again
+/// as part of its /16 if the rest of the /16 is heavy too.
+///
+/// Each level of `H` is summarized by a `MisraGries` with `K` counters, which undercounts by at
+/// most `n / (K + 1)` for a stream of length `n`. A conditioned count subtracts the estimates of
+/// the heavy descendants, so it is off by at most that much for each level it spans.
+#[derive(Clone, Debug)]
+pub struct HierarchicalHeavyHitters<T, H, const K: usize> {
+    hierarchy: PhantomData<H>,
+    levels: Vec<MisraGries<T, K>>,
+    len: u64,
+}
+
+impl<T, H, const K: usize> HierarchicalHeavyHitters<T, H, K> {
+    /// Number of elements seen.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T: Hash + Eq + Clone, H: Hierarchy<T>, const K: usize> StreamProcessor<T>
+    for HierarchicalHeavyHitters<T, H, K>
+{
+    fn new() -> Self {
+        assert_ne!(H::LEVELS, 0);
+        Self {
+            hierarchy: PhantomData,
+            levels: (0..H::LEVELS).map(|_| MisraGries::new()).collect(),
+            len: 0,
+        }
+    }
+    fn process(&mut self, v: T) {
+        self.len += 1;
+        for (level, mg) in self.levels.iter_mut().enumerate() {
+            mg.process(H::generalize(&v, level));
+        }
+    }
+    /// `(level, prefix, conditioned count)` of each heavy hitter, from the lowest level up.
+    type Result = Vec<(usize, T, usize)>;
+    /// Fraction of the stream which a conditioned count must reach.
+    type Args = f64;
+    /// Reports prefixes bottom up. Each level passes up to its parents the counts it has
+    /// accounted for: a reported prefix passes its whole count, and any other passes the counts
+    /// of its heavy descendants.
+    fn query(&self, &phi: &f64) -> Self::Result {
+        assert!(phi > 0.0 && phi <= 1.0);
+        let threshold = (phi * self.len as f64).ceil() as usize;
+        let mut heavy = vec![];
+        let mut below = HashMap::<T, usize>::new();
+        for (level, mg) in self.levels.iter().enumerate() {
+            let mut above = HashMap::new();
+            let mut pass_up = |p: &T, c: usize| {
+                if level + 1 < H::LEVELS {
+                    *above.entry(H::generalize(p, level + 1)).or_insert(0) += c;
+                }
+            };
+            let mut reported = vec![];
+            for (p, &count) in &mg.counts {
+                let discount = below.remove(p).unwrap_or(0);
+                let conditioned = count.saturating_sub(discount);
+                if conditioned >= threshold {
+                    pass_up(p, count.max(discount));
+                    reported.push((level, p.clone(), conditioned));
+                } else {
+                    pass_up(p, discount);
+                }
+            }
+            // Heavy descendants of prefixes which this level no longer tracks.
+            for (p, discount) in below {
+                pass_up(&p, discount);
+            }
+            reported.sort_unstable_by_key(|&(_, _, c)| std::cmp::Reverse(c));
+            heavy.extend(reported);
+            below = above;
+        }
+        heavy
+    }
+}
+
+impl<T: Hash + Eq, H, const K: usize> Merge for HierarchicalHeavyHitters<T, H, K> {
+    fn merge(&mut self, other: Self) {
+        self.len += other.len;
+        for (a, b) in self.levels.iter_mut().zip(other.levels) {
+            a.merge(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_hierarchy {
+    use super::{HierarchicalHeavyHitters, Hierarchy, Ipv4Prefixes, Ipv6Prefixes};
+    use crate::{Merge, SketchExt, StreamProcessor};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn generalizes_prefixes() {
+        let v4 = Ipv4Addr::new(10, 1, 2, 3);
+        assert_eq!(Ipv4Prefixes::generalize(&v4, 0), v4);
+        assert_eq!(Ipv4Prefixes::generalize(&v4, 2), Ipv4Addr::new(10, 1, 0, 0));
+        let v6 = "2001:db8:1:2:3::4".parse::<Ipv6Addr>().unwrap();
+        let site = "2001:db8:1::".parse::<Ipv6Addr>().unwrap();
+        assert_eq!(Ipv6Prefixes::generalize(&v6, 2), site);
+    }
+
+    /// 30% from 10.0.0.1, 30% from other hosts of 10.0.0.0/24, 20% from the rest of 10.0.0.0/16,
+    /// and 20% from addresses all over.
+    fn traffic(i: u32) -> Ipv4Addr {
+        match i % 10 {
+            0..=2 => Ipv4Addr::new(10, 0, 0, 1),
+            3..=5 => Ipv4Addr::new(10, 0, 0, 2 + (i % 250) as u8),
+            6..=7 => Ipv4Addr::new(10, 0, 1 + (i % 200) as u8, i as u8),
+            _ => Ipv4Addr::from(i.wrapping_mul(2_654_435_761)),
+        }
+    }
+
+    #[test]
+    fn discounts_heavy_descendants() {
+        type Hhh = HierarchicalHeavyHitters<Ipv4Addr, Ipv4Prefixes, 32>;
+        let mut hhh = (0..5000).map(traffic).sketch::<Hhh>();
+        hhh.merge((5000..10_000).map(traffic).sketch());
+        assert_eq!(hhh.len(), 10_000);
+        let heavy = hhh.query(&0.1);
+        let prefixes = heavy.iter().map(|&(l, p, _)| (l, p)).collect::<Vec<_>>();
+        let net = Ipv4Addr::new(10, 0, 0, 0);
+        let expected = [(0, Ipv4Addr::new(10, 0, 0, 1)), (1, net), (2, net)];
+        assert_eq!(prefixes, expected);
+        // each level undercounts by at most 10_000 / 33
+        let near = |c: usize, exact: usize| (c as f64 - exact as f64).abs() <= 2.0 * 304.0;
+        assert!(near(heavy[0].2, 3000));
+        assert!(near(heavy[1].2, 3000));
+        assert!(near(heavy[2].2, 2000));
+    }
+}
@@ -1,7 +1,10 @@
-use super::StreamProcessor;
-use std::collections::hash_map::{Entry, RandomState};
+use super::estimate::check_confidence;
+use super::hash::CarterWegman;
+use super::{seed, Estimate, Estimator, HashFunction, Merge, StreamProcessor, HASH_BATCH};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::hash::{BuildHasher, Hash};
+use std::f64::consts::E;
+use std::hash::Hash;
 use std::marker::PhantomData;
 
 #[derive(Clone, Debug)]
@@ -28,9 +31,9 @@ impl<T: Hash + Eq + Clone, const K: usize> StreamProcessor<T> for MisraGries<T,
                 v.insert(1);
             }
             _ => {
-                self.counts.drain_filter(|_, v| {
+                self.counts.retain(|_, v| {
                     *v -= 1;
-                    *v == 0
+                    *v != 0
                 });
             }
         }
@@ -44,19 +47,39 @@ impl<T: Hash + Eq + Clone, const K: usize> StreamProcessor<T> for MisraGries<T,
     }
 }
 
+impl<T: Hash + Eq, const K: usize> Merge for MisraGries<T, K> {
+    /// Adds the counts, then subtracts the (K+1)-th largest count from all of them, which keeps
+    /// the same error guarantee as a single summary of both streams.
+    fn merge(&mut self, other: Self) {
+        for (t, c) in other.counts {
+            *self.counts.entry(t).or_insert(0) += c;
+        }
+        if self.counts.len() <= K {
+            return;
+        }
+        let mut counts = self.counts.values().copied().collect::<Vec<_>>();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        let cutoff = counts[K];
+        self.counts.retain(|_, v| {
+            *v = v.saturating_sub(cutoff);
+            *v != 0
+        });
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct CountMin<T, S = RandomState, const BYTES: usize = 32, const H: usize = 16> {
+pub struct CountMin<T, S = CarterWegman, const BYTES: usize = 32, const H: usize = 16> {
     marker: PhantomData<T>,
     pub buckets: [(S, [u32; BYTES]); H],
 }
 
-impl<T: Hash, S: BuildHasher + Default, const B: usize, const H: usize> StreamProcessor<T>
+impl<T, S: HashFunction<T>, const B: usize, const H: usize> StreamProcessor<T>
     for CountMin<T, S, B, H>
 {
     fn new() -> Self {
         assert_ne!(H, 0);
         assert_ne!(B, 0);
-        let buckets = std::array::from_fn(|_| (Default::default(), [0; B]));
+        let buckets = std::array::from_fn(|_| (S::from_seed(seed()), [0; B]));
         Self {
             marker: Default::default(),
             buckets,
@@ -66,7 +89,19 @@ impl<T: Hash, S: BuildHasher + Default, const B: usize, const H: usize> StreamPr
         assert_ne!(H, 0);
         assert_ne!(B, 0);
         for (s, bucket) in self.buckets.iter_mut() {
-            bucket[s.hash_one(&t) as usize % B] += 1;
+            bucket[s.hash(&t) as usize % B] += 1;
+        }
+    }
+    /// Hashes the batch for all rows together, which lets hash families share work between
+    /// rows, and updates one row at a time so each row stays in cache.
+    fn process_batch(&mut self, ts: &[T])
+    where
+        T: Clone,
+    {
+        let (hashers, mut rows): (Vec<_>, Vec<_>) =
+            self.buckets.iter_mut().map(|(s, b)| (&*s, b)).unzip();
+        for ts in ts.chunks(HASH_BATCH) {
+            S::hash_many(&hashers, ts, |i, h| rows[i][h as usize % B] += 1);
         }
     }
 
@@ -77,8 +112,244 @@ impl<T: Hash, S: BuildHasher + Default, const B: usize, const H: usize> StreamPr
         assert_ne!(B, 0);
         self.buckets
             .iter()
-            .map(|(s, bucket)| bucket[s.hash_one(t) as usize % B] as usize)
+            .map(|(s, bucket)| bucket[s.hash(t) as usize % B] as usize)
+            .min()
+            .unwrap()
+    }
+}
+
+impl<T, S: HashFunction<T>, const B: usize, const H: usize> Estimator<T> for CountMin<T, S, B, H> {
+    fn estimate(&self, t: &T, confidence: f64) -> Estimate {
+        let total: u64 = self.buckets[0].1.iter().map(|&c| u64::from(c)).sum();
+        count_min_bounds(self.query(t) as f64, total as f64, B, H, confidence)
+    }
+}
+
+impl<T, S, const B: usize, const H: usize> Merge for CountMin<T, S, B, H> {
+    fn merge(&mut self, other: Self) {
+        for ((_, a), (_, b)) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            for (a, b) in a.iter_mut().zip(b.iter()) {
+                *a += b;
+            }
+        }
+    }
+}
+
+/// Count-Min never underestimates, and by Markov's inequality each row overestimates by more
+/// than `x` with probability at most `total / (width * x)`, independently across rows.
+pub(crate) fn count_min_bounds(
+    value: f64,
+    total: f64,
+    width: usize,
+    depth: usize,
+    confidence: f64,
+) -> Estimate {
+    let err = count_min_error(total, width, depth, confidence);
+    Estimate {
+        value,
+        lower: (value - err).max(0.0),
+        upper: value,
+        confidence,
+    }
+}
+
+/// Overestimate which one Count-Min query stays within at the given confidence.
+pub(crate) fn count_min_error(total: f64, width: usize, depth: usize, confidence: f64) -> f64 {
+    check_confidence(confidence);
+    total / width as f64 * (1.0 - confidence).powf(-(depth as f64).recip())
+}
+
+/// Width and depth of a Count-Min sketch whose estimates exceed the true count by at most
+/// `epsilon` times the length of the stream, with probability at least `1 - delta`.
+pub(crate) fn count_min_dimensions(epsilon: f64, delta: f64) -> (usize, usize) {
+    assert!(epsilon > 0.0);
+    assert!(delta > 0.0 && delta < 1.0);
+    let width = (E / epsilon).ceil() as usize;
+    let depth = delta.recip().ln().ceil().max(1.0) as usize;
+    (width, depth)
+}
+
+/// Count-Min sketch whose width and depth are chosen at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynCountMin<T, S = CarterWegman> {
+    marker: PhantomData<T>,
+    width: usize,
+    hashers: Vec<S>,
+    /// Counters for each hash function, stored row after row.
+    pub counts: Vec<u32>,
+}
+
+impl<T, S: HashFunction<T>> DynCountMin<T, S> {
+    /// Sizes the sketch so that an estimate exceeds the true count by at most `epsilon` times
+    /// the length of the stream, with probability at least `1 - delta`.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        let (width, depth) = count_min_dimensions(epsilon, delta);
+        Self::with_dimensions(width, depth)
+    }
+    /// Creates a sketch with `depth` rows of `width` counters each.
+    pub fn with_dimensions(width: usize, depth: usize) -> Self {
+        assert_ne!(width, 0);
+        assert_ne!(depth, 0);
+        Self {
+            marker: PhantomData,
+            width,
+            hashers: (0..depth).map(|_| S::from_seed(seed())).collect(),
+            counts: vec![0; width * depth],
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn depth(&self) -> usize {
+        self.hashers.len()
+    }
+}
+
+impl<T, S: HashFunction<T>> StreamProcessor<T> for DynCountMin<T, S> {
+    /// Creates a sketch with `epsilon = 0.001` and `delta = 0.01`.
+    fn new() -> Self {
+        Self::with_error(0.001, 0.01)
+    }
+    fn process(&mut self, t: T) {
+        let w = self.width;
+        for (s, row) in self.hashers.iter().zip(self.counts.chunks_exact_mut(w)) {
+            row[s.hash(&t) as usize % w] += 1;
+        }
+    }
+    /// Hashes the batch for all rows together, which lets hash families share work between
+    /// rows, and updates one row at a time so each row stays in cache.
+    fn process_batch(&mut self, ts: &[T])
+    where
+        T: Clone,
+    {
+        let w = self.width;
+        let hashers = self.hashers.iter().collect::<Vec<_>>();
+        let mut rows = self.counts.chunks_exact_mut(w).collect::<Vec<_>>();
+        for ts in ts.chunks(HASH_BATCH) {
+            S::hash_many(&hashers, ts, |i, h| rows[i][h as usize % w] += 1);
+        }
+    }
+
+    type Result = usize;
+    type Args = T;
+    fn query(&self, t: &T) -> Self::Result {
+        let w = self.width;
+        self.hashers
+            .iter()
+            .zip(self.counts.chunks_exact(w))
+            .map(|(s, row)| row[s.hash(t) as usize % w] as usize)
             .min()
             .unwrap()
     }
 }
+
+impl<T, S: HashFunction<T>> Estimator<T> for DynCountMin<T, S> {
+    fn estimate(&self, t: &T, confidence: f64) -> Estimate {
+        let total: u64 = self.counts[..self.width]
+            .iter()
+            .map(|&c| u64::from(c))
+            .sum();
+        count_min_bounds(
+            self.query(t) as f64,
+            total as f64,
+            self.width,
+            self.depth(),
+            confidence,
+        )
+    }
+}
+
+impl<T, S> Merge for DynCountMin<T, S> {
+    fn merge(&mut self, other: Self) {
+        assert_eq!(self.counts.len(), other.counts.len());
+        assert_eq!(self.width, other.width);
+        for (a, b) in self.counts.iter_mut().zip(other.counts) {
+            *a += b;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_high_freq {
+    use super::{DynCountMin, MisraGries};
+    use crate::{Estimator, Merge, StreamProcessor};
+
+    #[test]
+    fn merged_count_min() {
+        let empty = DynCountMin::<u32>::with_error(0.1, 0.1);
+        let (mut all, mut a, mut b) = (empty.clone(), empty.clone(), empty);
+        for i in 0..100 {
+            all.process(i % 7);
+            if i < 40 {
+                a.process(i % 7);
+            } else {
+                b.process(i % 7);
+            }
+        }
+        a.merge(b);
+        assert_eq!(a, all);
+    }
+
+    #[test]
+    fn batch_matches_single() {
+        let items = (0..1000).map(|i| i * i % 97).collect::<Vec<u64>>();
+        let mut single = DynCountMin::<u64>::with_error(0.05, 0.05);
+        let mut batch = single.clone();
+        for &i in &items {
+            single.process(i);
+        }
+        batch.process_batch(&items);
+        assert_eq!(single, batch);
+    }
+
+    #[test]
+    fn merged_majority() {
+        let mut a = MisraGries::<u32, 2>::new();
+        let mut b = MisraGries::<u32, 2>::new();
+        for i in 0..30 {
+            a.process(if i % 2 == 0 { 1 } else { i });
+            b.process(if i % 3 == 0 { 2 } else { 1 });
+        }
+        a.merge(b);
+        assert!(a.counts.contains_key(&1));
+        assert!(a.counts.len() <= 2);
+    }
+
+    #[test]
+    fn sized_from_error() {
+        let cm = DynCountMin::<u32>::with_error(0.01, 0.01);
+        assert_eq!(cm.width(), 272);
+        assert_eq!(cm.depth(), 5);
+    }
+
+    #[test]
+    fn total_exceeds_counters() {
+        let mut cm = DynCountMin::<u32>::with_dimensions(4, 1);
+        cm.counts.iter_mut().for_each(|c| *c = u32::MAX / 2);
+        let est = cm.estimate(&0, 0.5);
+        assert_eq!(est.value, (u32::MAX / 2) as f64);
+        assert_eq!(est.lower, 0.0);
+    }
+
+    quickcheck! {
+      fn count_min_overestimates(x: Vec<u8>) -> bool {
+        let mut cm = DynCountMin::<u8>::with_error(0.1, 0.01);
+        for &v in &x {
+            cm.process(v);
+        }
+        x.iter().all(|v| {
+            let exact = x.iter().filter(|&u| u == v).count();
+            let approx = cm.query(v);
+            exact <= approx && approx <= exact + x.len() / 5
+        })
+      }
+      fn count_min_estimate(x: Vec<u8>) -> bool {
+        let mut cm = DynCountMin::<u8>::with_dimensions(16, 4);
+        for &v in &x {
+            cm.process(v);
+        }
+        let exact = x.iter().filter(|&&u| u == 0).count();
+        cm.estimate(&0, 0.999).contains(exact as f64)
+      }
+    }
+}
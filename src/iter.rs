@@ -0,0 +1,66 @@
+use super::StreamProcessor;
+
+/// Extends iterators with methods which feed their items to stream processors.
+pub trait SketchExt: Iterator + Sized {
+    /// Processes every item with a new processor, and returns the processor to be queried.
+    /// ```
+    /// use streamo::{distinct::HyperLogLog, SketchExt, StreamProcessor};
+    /// let hll = (0..1000).map(|i| i % 10).sketch::<HyperLogLog<_>>();
+    /// assert_eq!(hll.query(&()), 10);
+    /// ```
+    fn sketch<P: StreamProcessor<Self::Item>>(self) -> P {
+        let mut p = P::new();
+        for v in self {
+            p.process(v);
+        }
+        p
+    }
+    /// Feeds a clone of every item to `processor`, while still yielding the items.
+    /// ```
+    /// use streamo::{distinct::HyperLogLog, SketchExt, StreamProcessor};
+    /// let mut distinct = HyperLogLog::<_>::new();
+    /// let evens = (0..100)
+    ///     .map(|i| i % 10)
+    ///     .inspect_into(&mut distinct)
+    ///     .filter(|i| i % 2 == 0)
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(evens.len(), 50);
+    /// assert_eq!(distinct.query(&()), 10);
+    /// ```
+    fn inspect_into<P: StreamProcessor<Self::Item>>(
+        self,
+        processor: &mut P,
+    ) -> InspectInto<'_, Self, P>
+    where
+        Self::Item: Clone,
+    {
+        InspectInto {
+            iter: self,
+            processor,
+        }
+    }
+}
+
+impl<I: Iterator> SketchExt for I {}
+
+/// Iterator returned by [`SketchExt::inspect_into`].
+#[derive(Debug)]
+pub struct InspectInto<'a, I, P> {
+    iter: I,
+    processor: &'a mut P,
+}
+
+impl<I: Iterator, P: StreamProcessor<I::Item>> Iterator for InspectInto<'_, I, P>
+where
+    I::Item: Clone,
+{
+    type Item = I::Item;
+    fn next(&mut self) -> Option<I::Item> {
+        let v = self.iter.next()?;
+        self.processor.process(v.clone());
+        Some(v)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
@@ -1,37 +1,58 @@
-#![feature(
-    int_log,
-    generic_const_exprs,
-    generic_arg_infer,
-    int_abs_diff,
-    adt_const_params,
-    array_from_fn,
-    associated_type_defaults,
-    generic_associated_types,
-    hash_drain_filter,
-    build_hasher_simple_hash_one
-)]
-#![allow(incomplete_features)]
-
 pub mod adapters;
 
 pub mod bloom;
 pub mod count;
+pub mod decay;
 pub mod distinct;
+pub mod estimate;
+pub mod hash;
+pub mod hierarchy;
 pub mod high_freq;
+pub mod iter;
+pub mod lsh;
+pub mod minhash;
+pub mod moments;
+pub mod parallel;
 pub mod quantile;
+pub mod range;
+pub mod sliding;
+pub mod stats;
+pub mod topk;
+pub mod window;
 //pub mod compactor;
 
 mod rand;
-pub use rand::rand;
+pub use estimate::{Estimate, Estimator};
+pub use iter::SketchExt;
+pub use rand::{rand, seed};
 
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
 
 /// Hash function is some function which maps from a set to an index.
-pub trait HashFunction<T> {
-    const CAP: usize;
-    fn hash(&mut self, v: T) -> usize;
+/// Implementors are families of functions, where each seed selects one member of the family.
+pub trait HashFunction<T: ?Sized> {
+    /// Number of low bits of the output which are uniformly distributed.
+    const BITS: u32 = 64;
+    /// Draws the member of this family selected by `seed`.
+    fn from_seed(seed: u64) -> Self;
+    fn hash(&self, v: &T) -> u64;
+    /// Hashes every value with each of `hashers`, calling `f(i, h)` with each hash `h` by
+    /// `hashers[i]`. All values are hashed by one member before moving on to the next.
+    /// `PolynomialHash` and `Tabulation` override this to fingerprint each value once for all of
+    /// their members; other families, including the default `XxHash64`, hash value by value.
+    fn hash_many(hashers: &[&Self], vs: &[T], mut f: impl FnMut(usize, u64))
+    where
+        Self: Sized,
+        T: Sized,
+    {
+        for (i, h) in hashers.iter().enumerate() {
+            for v in vs {
+                f(i, h.hash(v));
+            }
+        }
+    }
 }
 
 /// StreamProcessor abstracts over something that processes a stream.
@@ -40,9 +61,21 @@ pub trait StreamProcessor<T> {
     fn new() -> Self;
     fn process(&mut self, v: T);
     type Result;
-    type Args = ();
+    /// Arguments passed to `query`, `()` for processors which summarize the whole stream.
+    type Args;
     fn query(&self, args: &Self::Args) -> Self::Result;
 
+    /// Processes many elements at once. Processors override this when they can share work
+    /// between elements, such as hashing them all before touching their counters.
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        for v in vs {
+            self.process(v.clone());
+        }
+    }
+
     fn apply(iter: impl Iterator<Item = T>, args: &Self::Args) -> Self::Result
     where
         Self: Sized,
@@ -55,6 +88,19 @@ pub trait StreamProcessor<T> {
     }
 }
 
+/// Number of elements hashed together by batched processing, which bounds the intermediate
+/// state hash families keep for a batch.
+const HASH_BATCH: usize = 64;
+
+/// A stream processor whose states from two disjoint streams can be combined.
+pub trait Merge {
+    /// Combines `other` into `self`, as if `self` had also processed the stream `other` did.
+    /// Sketches can only be merged if they share hash functions, so both should be clones of
+    /// the same instance. Clones share hash functions but not random generators, so that the
+    /// random choices of clones processing different streams stay independent.
+    fn merge(&mut self, other: Self);
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 struct Bitmap<const BYTES: usize> {
     pub bytes: [u8; BYTES],
@@ -87,4 +133,41 @@ impl<const B: usize> Bitmap<B> {
         let idx = i % 8;
         1 & (self.bytes[bucket] >> idx) == 1
     }
+    pub fn union(&mut self, other: &Self) {
+        for (a, b) in self.bytes.iter_mut().zip(other.bytes.iter()) {
+            *a |= b;
+        }
+    }
+}
+
+/// Heap allocated bitmap whose length is chosen at runtime.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct BitVec {
+    words: Vec<u64>,
+    bits: usize,
+}
+
+impl BitVec {
+    pub fn new(bits: usize) -> Self {
+        assert_ne!(bits, 0);
+        let words = vec![0; bits.div_ceil(64)];
+        Self { words, bits }
+    }
+    pub fn len(&self) -> usize {
+        self.bits
+    }
+    pub fn set(&mut self, i: usize) {
+        assert!(i < self.bits);
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+    pub fn get(&self, i: usize) -> bool {
+        assert!(i < self.bits);
+        1 & (self.words[i / 64] >> (i % 64)) == 1
+    }
+    pub fn union(&mut self, other: &Self) {
+        assert_eq!(self.bits, other.bits);
+        for (a, b) in self.words.iter_mut().zip(other.words.iter()) {
+            *a |= b;
+        }
+    }
 }
@@ -0,0 +1,212 @@
+use super::minhash::jaccard;
+use super::rand::SplitMix64;
+use super::{Merge, StreamProcessor};
+use std::collections::{HashMap, HashSet};
+
+/// Probability that two signatures with Jaccard similarity `s` share at least one of `bands`
+/// bands of `rows` slots.
+fn collision(s: f64, bands: usize, rows: usize) -> f64 {
+    1.0 - (1.0 - s.powi(rows as i32)).powi(bands as i32)
+}
+
+/// Integrates `f` over `[a, b]` with the midpoint rule.
+fn integrate(f: impl Fn(f64) -> f64, a: f64, b: f64) -> f64 {
+    const STEPS: usize = 100;
+    let step = (b - a) / STEPS as f64;
+    (0..STEPS)
+        .map(|i| f(a + (i as f64 + 0.5) * step))
+        .sum::<f64>()
+        * step
+}
+
+/// Index of MinHash signatures of `K` slots, which finds the signatures similar to a given one
+/// without comparing it to all of them. Signatures are split into bands of rows, and two of them
+/// become candidates if they agree on every row of some band. Candidates are then compared, and
+/// only those with an estimated Jaccard similarity of at least the threshold are returned.
+///
+/// Signatures must come from clones of one `MinHash` or `OnePermutationMinHash`.
+#[derive(Debug, Clone)]
+pub struct LshIndex<Id, const K: usize = 128> {
+    threshold: f64,
+    bands: usize,
+    rows: usize,
+    entries: Vec<(Id, [u64; K])>,
+    /// For each band, the entries with each hash of its rows.
+    buckets: Vec<HashMap<u64, Vec<usize>>>,
+}
+
+impl<Id, const K: usize> LshIndex<Id, K> {
+    /// Chooses the bands and rows for `threshold` which minimize the sum of the probability of
+    /// a candidate below it and of a miss above it, integrated over the similarities.
+    pub fn with_threshold(threshold: f64) -> Self {
+        assert!(threshold > 0.0 && threshold < 1.0);
+        let mut best = (f64::INFINITY, 1, K);
+        for bands in 1..=K {
+            for rows in 1..=K / bands {
+                let false_positives = integrate(|s| collision(s, bands, rows), 0.0, threshold);
+                let false_negatives =
+                    integrate(|s| 1.0 - collision(s, bands, rows), threshold, 1.0);
+                let error = false_positives + false_negatives;
+                if error < best.0 {
+                    best = (error, bands, rows);
+                }
+            }
+        }
+        Self::with_bands(threshold, best.1, best.2)
+    }
+    /// Splits signatures into `bands` bands of `rows` slots each.
+    pub fn with_bands(threshold: f64, bands: usize, rows: usize) -> Self {
+        assert!((0.0..=1.0).contains(&threshold));
+        assert!(bands != 0 && rows != 0 && bands * rows <= K);
+        Self {
+            threshold,
+            bands,
+            rows,
+            entries: vec![],
+            buckets: vec![HashMap::new(); bands],
+        }
+    }
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    /// Number of signatures in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Hashes of each band of a signature.
+    fn band_keys(&self, signature: &[u64; K]) -> Vec<u64> {
+        let rows = signature.chunks_exact(self.rows).take(self.bands);
+        rows.map(|row| row.iter().fold(0, |acc, &h| SplitMix64::mix(acc ^ h)))
+            .collect()
+    }
+    /// Indexes of the entries which share a band with `signature`, without duplicates.
+    fn candidates(&self, signature: &[u64; K]) -> HashSet<usize> {
+        self.band_keys(signature)
+            .into_iter()
+            .zip(&self.buckets)
+            .filter_map(|(key, buckets)| buckets.get(&key))
+            .flatten()
+            .copied()
+            .collect()
+    }
+    /// Pairs of signatures in the index which share a band and whose estimated similarity is at
+    /// least the threshold, with the earlier inserted first.
+    pub fn candidate_pairs(&self) -> Vec<(&Id, &Id)> {
+        let mut pairs = HashSet::new();
+        for bucket in self.buckets.iter().flat_map(|b| b.values()) {
+            for (i, &a) in bucket.iter().enumerate() {
+                pairs.extend(bucket[i + 1..].iter().map(|&b| (a, b)));
+            }
+        }
+        let mut pairs = pairs
+            .into_iter()
+            .filter(|&(a, b)| self.similar(&self.entries[a].1, &self.entries[b].1))
+            .collect::<Vec<_>>();
+        pairs.sort_unstable();
+        let id = |i: usize| &self.entries[i].0;
+        pairs.into_iter().map(|(a, b)| (id(a), id(b))).collect()
+    }
+    fn similar(&self, a: &[u64; K], b: &[u64; K]) -> bool {
+        jaccard(a, b) >= self.threshold
+    }
+}
+
+impl<Id: Clone, const K: usize> StreamProcessor<(Id, [u64; K])> for LshIndex<Id, K> {
+    /// Creates an index for a threshold of 0.5.
+    fn new() -> Self {
+        Self::with_threshold(0.5)
+    }
+    fn process(&mut self, (id, signature): (Id, [u64; K])) {
+        let i = self.entries.len();
+        let keys = self.band_keys(&signature);
+        for (key, buckets) in keys.into_iter().zip(self.buckets.iter_mut()) {
+            buckets.entry(key).or_default().push(i);
+        }
+        self.entries.push((id, signature));
+    }
+    /// Ids of the similar signatures, in the order they were inserted.
+    type Result = Vec<Id>;
+    type Args = [u64; K];
+    fn query(&self, signature: &[u64; K]) -> Vec<Id> {
+        let mut found = self
+            .candidates(signature)
+            .into_iter()
+            .filter(|&i| self.similar(signature, &self.entries[i].1))
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+        found
+            .into_iter()
+            .map(|i| self.entries[i].0.clone())
+            .collect()
+    }
+}
+
+impl<Id: Clone, const K: usize> Merge for LshIndex<Id, K> {
+    /// Inserts the signatures of `other` after those of `self`.
+    fn merge(&mut self, other: Self) {
+        assert_eq!((self.bands, self.rows), (other.bands, other.rows));
+        for entry in other.entries {
+            self.process(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_lsh {
+    use super::{collision, LshIndex};
+    use crate::minhash::MinHash;
+    use crate::{Merge, StreamProcessor};
+
+    #[test]
+    fn chooses_bands_around_threshold() {
+        for threshold in [0.3, 0.5, 0.8] {
+            let index = LshIndex::<u32>::with_threshold(threshold);
+            let (b, r) = (index.bands(), index.rows());
+            assert!(b * r <= 128);
+            assert!(collision(threshold - 0.2, b, r) < 0.5);
+            assert!(collision((threshold + 0.2).min(1.0), b, r) > 0.5);
+        }
+    }
+
+    #[test]
+    fn finds_near_duplicates() {
+        let empty = MinHash::<u32>::new();
+        let signature = |words: &mut dyn Iterator<Item = u32>| {
+            let mut m = empty.clone();
+            words.for_each(|w| m.process(w));
+            m.signature()
+        };
+        // Documents 0 and 1 share 95 of their 105 distinct words, and the rest share none.
+        let docs = (0..50u32)
+            .map(|d| match d {
+                0 => signature(&mut (0..100)),
+                1 => signature(&mut (5..105)),
+                d => signature(&mut (d * 1000..d * 1000 + 100)),
+            })
+            .collect::<Vec<_>>();
+        let mut index = LshIndex::<u32>::with_threshold(0.7);
+        let mut other = index.clone();
+        docs[..25]
+            .iter()
+            .zip(0..)
+            .for_each(|(&s, d)| index.process((d, s)));
+        docs[25..]
+            .iter()
+            .zip(25..)
+            .for_each(|(&s, d)| other.process((d, s)));
+        index.merge(other);
+        assert_eq!(index.len(), 50);
+        assert_eq!(index.candidate_pairs(), [(&0, &1)]);
+        assert_eq!(index.query(&docs[1]), [0, 1]);
+        assert_eq!(index.query(&docs[7]), [7]);
+    }
+}
@@ -0,0 +1,296 @@
+use super::estimate::check_confidence;
+use super::hash::XxHash64;
+use super::rand::SplitMix64;
+use super::{seed, Estimate, Estimator, HashFunction, Merge, StreamProcessor, HASH_BATCH};
+use std::marker::PhantomData;
+
+/// Slot of a signature which no element has reached.
+const EMPTY: u64 = u64::MAX;
+
+/// Fraction of slots where two signatures agree, which is NaN if both sets are empty.
+pub(crate) fn jaccard(a: &[u64], b: &[u64]) -> f64 {
+    assert_eq!(a.len(), b.len());
+    if a.iter().chain(b).all(|&h| h == EMPTY) {
+        return f64::NAN;
+    }
+    let same = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    same as f64 / a.len() as f64
+}
+
+/// Bounds a fraction of `k` agreeing slots with Hoeffding's inequality, treating the slots as
+/// independent trials.
+fn jaccard_bounds(value: f64, k: usize, confidence: f64) -> Estimate {
+    check_confidence(confidence);
+    let eps = ((2.0 / (1.0 - confidence)).ln() / (2.0 * k as f64)).sqrt();
+    Estimate {
+        value,
+        lower: (value - eps).max(0.0),
+        upper: (value + eps).min(1.0),
+        confidence,
+    }
+}
+
+/// Keeps the smallest hash of a stream for each of `K` independent hash functions. Two streams
+/// hashed with the same functions share a minimum exactly when the smallest hash of their union
+/// belongs to both, so the fraction of shared minima estimates their Jaccard similarity
+/// `|A ∩ B| / |A ∪ B|` with standard deviation at most `1 / (2 sqrt(K))`.
+///
+/// Sketches are comparable only if they are clones of one sketch, which share their hash
+/// functions. Each element costs `K` hashes; `OnePermutationMinHash` needs one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinHash<T, S = XxHash64, const K: usize = 128> {
+    marker: PhantomData<T>,
+    hashers: [S; K],
+    mins: [u64; K],
+}
+
+impl<T, S: HashFunction<T>, const K: usize> MinHash<T, S, K> {
+    /// Minimum of each hash function, or `u64::MAX` for an empty stream.
+    pub fn signature(&self) -> [u64; K] {
+        self.mins
+    }
+    /// Estimates the Jaccard similarity of the two streams.
+    pub fn jaccard(&self, other: &Self) -> f64 {
+        jaccard(&self.mins, &other.mins)
+    }
+    /// Bounds the Jaccard similarity of the two streams with Hoeffding's inequality.
+    pub fn estimate_jaccard(&self, other: &Self, confidence: f64) -> Estimate {
+        jaccard_bounds(self.jaccard(other), K, confidence)
+    }
+    /// Estimates the number of distinct elements in both streams together.
+    pub fn union_cardinality(&self, other: &Self) -> usize
+    where
+        Self: Clone,
+    {
+        let mut union = self.clone();
+        union.merge(other.clone());
+        union.query(&())
+    }
+}
+
+impl<T, S: HashFunction<T>, const K: usize> StreamProcessor<T> for MinHash<T, S, K> {
+    fn new() -> Self {
+        assert!(K > 2, "MinHash needs more than 2 hash functions");
+        Self {
+            marker: PhantomData,
+            hashers: std::array::from_fn(|_| S::from_seed(seed())),
+            mins: [EMPTY; K],
+        }
+    }
+    fn process(&mut self, v: T) {
+        for (s, min) in self.hashers.iter().zip(self.mins.iter_mut()) {
+            *min = (*min).min(s.hash(&v));
+        }
+    }
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        let hashers = self.hashers.iter().collect::<Vec<_>>();
+        let mins = &mut self.mins;
+        for vs in vs.chunks(HASH_BATCH) {
+            S::hash_many(&hashers, vs, |i, h| mins[i] = mins[i].min(h));
+        }
+    }
+    type Result = usize;
+    type Args = ();
+    /// Estimates the number of distinct elements. The minimum of `n` uniform hashes scaled to
+    /// `[0, 1)` is about exponential with rate `n`, so `(K - 1)` over the sum of the scaled
+    /// minima is an unbiased estimate of `n`.
+    fn query(&self, (): &()) -> usize {
+        if self.mins[0] == EMPTY {
+            return 0;
+        }
+        let scale = 2f64.powi(S::BITS as i32);
+        let sum = self.mins.iter().map(|&m| m as f64 / scale).sum::<f64>();
+        ((K - 1) as f64 / sum).round() as usize
+    }
+}
+
+impl<T, S: HashFunction<T>, const K: usize> Estimator<T> for MinHash<T, S, K> {
+    /// The estimate of the cardinality has a relative standard deviation of `1 / sqrt(K - 2)`.
+    fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+        let value = self.query(&()) as f64;
+        let std_dev = (value / ((K - 2) as f64).sqrt()).max(1.0);
+        Estimate::chebyshev(value, std_dev, confidence)
+    }
+}
+
+impl<T, S, const K: usize> Merge for MinHash<T, S, K> {
+    fn merge(&mut self, other: Self) {
+        for (a, b) in self.mins.iter_mut().zip(other.mins) {
+            *a = (*a).min(b);
+        }
+    }
+}
+
+/// MinHash with a single hash function, whose hashes are split into `K` bins by their
+/// remainder, keeping the smallest quotient of each bin. With fewer than about `K ln K` distinct
+/// elements some bins stay empty, and the signature fills each from a bin picked by a probe
+/// sequence shared between clones, the optimal densification of Shrivastava, so that it still
+/// estimates Jaccard similarity like `K` hash functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnePermutationMinHash<T, S = XxHash64, const K: usize = 128> {
+    marker: PhantomData<T>,
+    hasher: S,
+    /// Seed of the probe sequences for empty bins.
+    densify: u64,
+    bins: [u64; K],
+}
+
+impl<T, S: HashFunction<T>, const K: usize> OnePermutationMinHash<T, S, K> {
+    /// Minimum of each bin, with each empty bin taking the minimum of the first non-empty bin
+    /// in its probe sequence. It is all `u64::MAX` for an empty stream.
+    pub fn signature(&self) -> [u64; K] {
+        if self.bins.iter().all(|&b| b == EMPTY) {
+            return self.bins;
+        }
+        std::array::from_fn(|i| {
+            let mut probes = SplitMix64::new(self.densify ^ SplitMix64::mix(i as u64));
+            let mut bin = self.bins[i];
+            while bin == EMPTY {
+                bin = self.bins[(probes.next_u64() % K as u64) as usize];
+            }
+            bin
+        })
+    }
+    /// Estimates the Jaccard similarity of the two streams.
+    pub fn jaccard(&self, other: &Self) -> f64 {
+        jaccard(&self.signature(), &other.signature())
+    }
+    /// Bounds the Jaccard similarity of the two streams with Hoeffding's inequality.
+    pub fn estimate_jaccard(&self, other: &Self, confidence: f64) -> Estimate {
+        jaccard_bounds(self.jaccard(other), K, confidence)
+    }
+    /// Estimates the number of distinct elements in both streams together.
+    pub fn union_cardinality(&self, other: &Self) -> usize
+    where
+        Self: Clone,
+    {
+        let mut union = self.clone();
+        union.merge(other.clone());
+        union.query(&())
+    }
+}
+
+impl<T, S: HashFunction<T>, const K: usize> StreamProcessor<T> for OnePermutationMinHash<T, S, K> {
+    fn new() -> Self {
+        assert!(K > 1, "OnePermutationMinHash needs more than one bin");
+        Self {
+            marker: PhantomData,
+            hasher: S::from_seed(seed()),
+            densify: seed(),
+            bins: [EMPTY; K],
+        }
+    }
+    fn process(&mut self, v: T) {
+        let h = self.hasher.hash(&v);
+        let bin = &mut self.bins[(h % K as u64) as usize];
+        *bin = (*bin).min(h / K as u64);
+    }
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        let bins = &mut self.bins;
+        for vs in vs.chunks(HASH_BATCH) {
+            S::hash_many(&[&self.hasher], vs, |_, h| {
+                let bin = &mut bins[(h % K as u64) as usize];
+                *bin = (*bin).min(h / K as u64);
+            });
+        }
+    }
+    type Result = usize;
+    type Args = ();
+    /// Estimates the number of distinct elements from the sum of the minima like `MinHash`, as
+    /// each bin holds about `n / K` elements. An empty bin only tells that its minimum is above
+    /// the largest, so it adds the largest to the sum but does not count as a sample. Like
+    /// `HyperLogLog`, it falls back to linear counting below `2.5 K` if some bins are empty,
+    /// which a few of them still are at about `K ln K` elements.
+    fn query(&self, (): &()) -> usize {
+        let k = K as f64;
+        let scale = 2f64.powi(S::BITS as i32) / k;
+        let sum = self
+            .bins
+            .iter()
+            .map(|&m| if m == EMPTY { 1.0 } else { m as f64 / scale })
+            .sum::<f64>();
+        let empty = self.bins.iter().filter(|&&b| b == EMPTY).count();
+        let estimate = k * (k - empty as f64 - 1.0) / sum;
+        if empty > 0 && estimate <= 2.5 * k {
+            return (k * (k / empty as f64).ln()).round() as usize;
+        }
+        estimate.round() as usize
+    }
+}
+
+impl<T, S, const K: usize> Merge for OnePermutationMinHash<T, S, K> {
+    fn merge(&mut self, other: Self) {
+        assert_eq!(self.densify, other.densify);
+        for (a, b) in self.bins.iter_mut().zip(other.bins) {
+            *a = (*a).min(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_minhash {
+    use super::{MinHash, OnePermutationMinHash};
+    use crate::{Estimator, Merge, StreamProcessor};
+
+    /// Sketches `0..600` and `300..900`, whose Jaccard similarity is 1/3.
+    fn overlapping<P: StreamProcessor<u32> + Clone>() -> (P, P) {
+        let mut a = P::new();
+        let mut b = a.clone();
+        (0..600).for_each(|i| a.process(i));
+        b.process_batch(&(300..900).collect::<Vec<_>>());
+        (a, b)
+    }
+
+    #[test]
+    fn k_hash() {
+        let (a, b) = overlapping::<MinHash<u32>>();
+        assert!(a.estimate_jaccard(&b, 0.99).contains(1.0 / 3.0));
+        assert!(a.estimate(&(), 0.9).contains(600.0));
+        let union = a.union_cardinality(&b) as f64;
+        assert!((union - 900.0).abs() < 0.3 * 900.0, "{}", union);
+        assert_eq!(a.jaccard(&a), 1.0);
+        assert!(MinHash::<u32>::new().jaccard(&MinHash::new()).is_nan());
+    }
+
+    #[test]
+    fn one_permutation() {
+        let (a, b) = overlapping::<OnePermutationMinHash<u32>>();
+        assert!(a.estimate_jaccard(&b, 0.99).contains(1.0 / 3.0));
+        let union = a.union_cardinality(&b) as f64;
+        assert!((union - 900.0).abs() < 0.3 * 900.0, "{}", union);
+        // A bin left empty by chance moves the estimate by about one sample, not to linear
+        // counting.
+        let mut sparse = a.clone();
+        sparse.merge(b);
+        sparse.bins[0] = u64::MAX;
+        let n = sparse.query(&()) as f64;
+        assert!((n - union).abs() < 0.1 * union, "{} {}", n, union);
+        // Few elements leave most bins empty, which densification fills.
+        let mut small = OnePermutationMinHash::<u32>::new();
+        let mut same = small.clone();
+        (0..10).for_each(|i| small.process(i));
+        (0..10).rev().for_each(|i| same.process(i));
+        assert!(small.signature().iter().all(|&h| h != u64::MAX));
+        assert_eq!(small.jaccard(&same), 1.0);
+        let n = small.query(&());
+        assert!((8..=12).contains(&n), "{}", n);
+    }
+
+    quickcheck! {
+      fn disjoint_and_identical(n: u8) -> bool {
+          let n = u32::from(n) + 50;
+          let mut a = OnePermutationMinHash::<u32, crate::hash::XxHash64, 64>::new();
+          let (mut b, mut c) = (a.clone(), a.clone());
+          (0..n).for_each(|i| a.process(i));
+          (n..2 * n).for_each(|i| b.process(i));
+          (0..n).for_each(|i| c.process(i));
+          a.jaccard(&b) < 0.25 && a.jaccard(&c) == 1.0
+      }
+    }
+}
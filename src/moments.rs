@@ -0,0 +1,370 @@
+use super::adapters::median;
+use super::hash::PolynomialHash;
+use super::rand::SplitMix64;
+use super::{seed, Estimate, Estimator, HashFunction, Merge, StreamProcessor, HASH_BATCH};
+use std::array;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::f64::consts::LOG2_E;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Tug-of-war sketch of Alon, Matias and Szegedy, which estimates the second frequency moment
+/// `F2` of a stream, the sum of its squared frequencies.
+///
+/// Each counter adds a random sign for every element, so its square estimates `F2` without bias
+/// and, given 4-wise independent signs, with variance at most `2 * F2^2`. The counters are
+/// boosted with the median of `M` means of `N` counters each, as in `MedianOfMeans`.
+///
+/// Two streams sketched by clones of one sketch share their signs, and the products of their
+/// counters estimate the inner product of their frequencies, which is the size of their join.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ams<T, S = PolynomialHash<4>, const N: usize = 16, const M: usize = 5> {
+    marker: PhantomData<T>,
+    hashers: [[S; N]; M],
+    counters: [[i64; N]; M],
+}
+
+impl<T, S: HashFunction<T>, const N: usize, const M: usize> Ams<T, S, N, M> {
+    fn sign(h: u64) -> i64 {
+        if h & 1 == 0 {
+            1
+        } else {
+            -1
+        }
+    }
+    /// Median of the means of each group's products of counters.
+    fn boosted(&self, other: &Self) -> f64 {
+        let mut means = array::from_fn::<_, M, _>(|i| {
+            let products = self.counters[i].iter().zip(&other.counters[i]);
+            products.map(|(&a, &b)| a as f64 * b as f64).sum::<f64>() / N as f64
+        });
+        median(&mut means)
+    }
+    /// Estimates the inner product of the frequencies of this stream and another one, which
+    /// was sketched by a clone of the same sketch.
+    pub fn inner_product(&self, other: &Self) -> f64 {
+        self.boosted(other)
+    }
+    /// Bounds the inner product with another stream's sketch. The variance of each product of
+    /// counters is at most `2 * F2(a) * F2(b)`, which is taken from both sketches' estimates.
+    pub fn estimate_inner_product(&self, other: &Self, confidence: f64) -> Estimate {
+        let f2 = self.boosted(self) * other.boosted(other);
+        let std_dev = (2.0 * f2 / N as f64).sqrt();
+        Estimate::median_of_means(self.inner_product(other), std_dev, M, confidence)
+    }
+}
+
+impl<T, S: HashFunction<T>, const N: usize, const M: usize> StreamProcessor<T> for Ams<T, S, N, M> {
+    fn new() -> Self {
+        assert_ne!(N, 0);
+        assert_ne!(M, 0);
+        Self {
+            marker: PhantomData,
+            hashers: array::from_fn(|_| array::from_fn(|_| S::from_seed(seed()))),
+            counters: [[0; N]; M],
+        }
+    }
+    fn process(&mut self, v: T) {
+        let pairs = self
+            .hashers
+            .iter()
+            .flatten()
+            .zip(self.counters.iter_mut().flatten());
+        for (s, c) in pairs {
+            *c += Self::sign(s.hash(&v));
+        }
+    }
+    /// Hashes the batch for all counters together, which lets hash families share work
+    /// between them.
+    fn process_batch(&mut self, vs: &[T])
+    where
+        T: Clone,
+    {
+        let hashers = self.hashers.iter().flatten().collect::<Vec<_>>();
+        let mut counters = self.counters.iter_mut().flatten().collect::<Vec<_>>();
+        for vs in vs.chunks(HASH_BATCH) {
+            S::hash_many(&hashers, vs, |i, h| *counters[i] += Self::sign(h));
+        }
+    }
+    type Result = f64;
+    type Args = ();
+    /// Estimates `F2`, the sum of squared frequencies.
+    fn query(&self, (): &()) -> f64 {
+        self.boosted(self)
+    }
+}
+
+impl<T, S: HashFunction<T>, const N: usize, const M: usize> Estimator<T> for Ams<T, S, N, M> {
+    /// Takes the standard deviation of each mean, `sqrt(2 / N) * F2`, from the estimate of
+    /// `F2`.
+    fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+        let value = self.query(&());
+        let std_dev = (2.0 / N as f64).sqrt() * value;
+        Estimate::median_of_means(value, std_dev, M, confidence)
+    }
+}
+
+impl<T, S, const N: usize, const M: usize> Merge for Ams<T, S, N, M> {
+    fn merge(&mut self, other: Self) {
+        let pairs = self
+            .counters
+            .iter_mut()
+            .flatten()
+            .zip(other.counters.iter().flatten());
+        for (a, b) in pairs {
+            *a += b;
+        }
+    }
+}
+
+/// Uniformly random positions of a stream, each with the number of times its item has occurred
+/// from there on, as used by the estimators of Alon, Matias and Szegedy.
+///
+/// There are `N * M` samples in `M` groups. Instead of drawing for each sample at every
+/// position, each sample draws the position at which it is replaced next, and only the items
+/// held by some sample are counted.
+#[derive(Clone, Debug)]
+struct Sampler<T, const N: usize, const M: usize> {
+    /// Item held by each sample, with its occurrences before it was sampled.
+    samples: Vec<Option<(T, u64)>>,
+    /// Occurrences of each held item, and the number of samples holding it.
+    counts: HashMap<T, (u64, usize)>,
+    /// Samples by the position at which they are replaced next, soonest first.
+    replacements: BinaryHeap<Reverse<(u64, usize)>>,
+    len: u64,
+    rng: SplitMix64,
+}
+
+impl<T: Hash + Eq + Clone, const N: usize, const M: usize> Sampler<T, N, M> {
+    fn new() -> Self {
+        assert_ne!(N, 0);
+        assert_ne!(M, 0);
+        Self {
+            samples: vec![None; N * M],
+            counts: HashMap::new(),
+            replacements: (0..N * M).map(|i| Reverse((1, i))).collect(),
+            len: 0,
+            rng: SplitMix64::new(seed()),
+        }
+    }
+    /// Draws the position after `len` at which a sample is replaced next. The sample is
+    /// replaced at each position `j` with probability `1 / j`, so it survives past `j` with
+    /// probability `len / j`. The uniform draw has 53 bits, so that no position is out of reach
+    /// on long streams.
+    fn next_replacement(&mut self) -> u64 {
+        let u = 1.0 - self.rng.next_f64();
+        (self.len as f64 / u) as u64 + 1
+    }
+    fn process(&mut self, v: T) {
+        self.len += 1;
+        if let Some((count, _)) = self.counts.get_mut(&v) {
+            *count += 1;
+        }
+        while let Some(&Reverse((next, i))) = self.replacements.peek() {
+            if next != self.len {
+                break;
+            }
+            self.replacements.pop();
+            if let Some((old, _)) = self.samples[i].take() {
+                let (_, holders) = self.counts.get_mut(&old).unwrap();
+                *holders -= 1;
+                if *holders == 0 {
+                    self.counts.remove(&old);
+                }
+            }
+            let (count, holders) = self.counts.entry(v.clone()).or_insert((1, 0));
+            *holders += 1;
+            self.samples[i] = Some((v.clone(), *count - 1));
+            let next = self.next_replacement();
+            self.replacements.push(Reverse((next, i)));
+        }
+    }
+    /// Mean of `f(r)` in each group, where `r` is each sample's number of occurrences.
+    fn means(&self, f: impl Fn(u64) -> f64) -> [f64; M] {
+        array::from_fn(|g| {
+            let group = &self.samples[g * N..(g + 1) * N];
+            let estimates = group.iter().flatten().map(|(item, before)| {
+                let (count, _) = self.counts[item];
+                f(count - before)
+            });
+            estimates.sum::<f64>() / N as f64
+        })
+    }
+}
+
+/// Estimates the frequency moments `Fk` of a stream, the sums of each frequency to the `k`-th
+/// power, using the sampling estimator of Alon, Matias and Szegedy. Queries take `k >= 1`.
+///
+/// Each of `N * M` samples picks a uniformly random position of the stream, and counts the
+/// occurrences `r` of its item from there on. Then `m * (r^k - (r - 1)^k)`, for a stream of
+/// length `m`, is an unbiased estimate of `Fk` whose variance is at most
+/// `k * m^(1 - 1/k) * Fk^2`. The estimates are boosted by the median of `M` means of `N`.
+/// Since samples do not depend on `k`, one instance answers for every `k`.
+#[derive(Clone, Debug)]
+pub struct FrequencyMoment<T, const N: usize = 64, const M: usize = 5> {
+    sampler: Sampler<T, N, M>,
+}
+
+impl<T, const N: usize, const M: usize> FrequencyMoment<T, N, M> {
+    /// Length of the stream.
+    pub fn len(&self) -> u64 {
+        self.sampler.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.sampler.len == 0
+    }
+}
+
+impl<T: Hash + Eq + Clone, const N: usize, const M: usize> StreamProcessor<T>
+    for FrequencyMoment<T, N, M>
+{
+    fn new() -> Self {
+        Self {
+            sampler: Sampler::new(),
+        }
+    }
+    fn process(&mut self, v: T) {
+        self.sampler.process(v);
+    }
+    type Result = f64;
+    type Args = u32;
+    /// Estimates `Fk` for `k >= 1`.
+    fn query(&self, &k: &u32) -> f64 {
+        assert_ne!(k, 0, "F0 is the number of distinct items, see HyperLogLog");
+        let m = self.sampler.len as f64;
+        let mut means = self.sampler.means(|r| {
+            let r = r as f64;
+            m * (r.powi(k as i32) - (r - 1.0).powi(k as i32))
+        });
+        median(&mut means)
+    }
+}
+
+impl<T: Hash + Eq + Clone, const N: usize, const M: usize> Estimator<T>
+    for FrequencyMoment<T, N, M>
+{
+    /// Takes the standard deviation of each mean from the variance bound with the estimate of
+    /// `Fk`, which is loose for most streams.
+    fn estimate(&self, k: &u32, confidence: f64) -> Estimate {
+        let value = self.query(k);
+        let k = *k as f64;
+        let var = k * (self.sampler.len as f64).powf(1.0 - k.recip()) / N as f64;
+        Estimate::median_of_means(value, var.sqrt() * value, M, confidence)
+    }
+}
+
+/// Estimates the empirical entropy of the items of a stream in bits, `sum(p * log2(1 / p))`
+/// over the fraction `p` of the stream taken by each item.
+///
+/// Uses the sampling estimator of Chakrabarti, Cormode and McGregor, with the same samples as
+/// `FrequencyMoment`. For a sample whose item occurs `r` times from its position on,
+/// `g(r) - g(r - 1)` with `g(r) = r * log2(m / r)` is an unbiased estimate of the entropy, which
+/// lies between `-log2(e)` and `log2(m)` for a stream of length `m`.
+#[derive(Clone, Debug)]
+pub struct Entropy<T, const N: usize = 256, const M: usize = 5> {
+    sampler: Sampler<T, N, M>,
+}
+
+impl<T: Hash + Eq + Clone, const N: usize, const M: usize> StreamProcessor<T> for Entropy<T, N, M> {
+    fn new() -> Self {
+        Self {
+            sampler: Sampler::new(),
+        }
+    }
+    fn process(&mut self, v: T) {
+        self.sampler.process(v);
+    }
+    type Result = f64;
+    type Args = ();
+    fn query(&self, (): &()) -> f64 {
+        let m = self.sampler.len as f64;
+        let g = |r: f64| if r == 0.0 { 0.0 } else { r * (m / r).log2() };
+        let mut means = self.sampler.means(|r| g(r as f64) - g(r as f64 - 1.0));
+        median(&mut means)
+    }
+}
+
+impl<T: Hash + Eq + Clone, const N: usize, const M: usize> Estimator<T> for Entropy<T, N, M> {
+    /// Each sample's estimate lies in a range of `log2(m) + log2(e)`, so its variance is at
+    /// most a quarter of that squared.
+    fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+        let range = (self.sampler.len.max(1) as f64).log2() + LOG2_E;
+        let std_dev = range / 2.0 / (N as f64).sqrt();
+        Estimate::median_of_means(self.query(&()), std_dev, M, confidence)
+    }
+}
+
+#[cfg(test)]
+mod test_moments {
+    use super::{Ams, Entropy, FrequencyMoment};
+    use crate::hash::PolynomialHash;
+    use crate::{Estimator, Merge, StreamProcessor};
+
+    /// Stream where `i` occurs `i` times, for `i` in `1..=n`.
+    fn staircase(n: u32) -> impl Iterator<Item = u32> {
+        (1..=n).flat_map(|i| std::iter::repeat(i).take(i as usize))
+    }
+
+    #[test]
+    fn second_moment() {
+        let ams = Ams::<u32>::apply(staircase(20), &());
+        let exact = (1..=20).map(|i| i * i).sum::<u32>() as f64;
+        let mut sketch = Ams::<u32>::new();
+        staircase(20).for_each(|v| sketch.process(v));
+        let e = sketch.estimate(&(), 0.99);
+        assert!(e.contains(exact), "{:?} {}", e, exact);
+        assert!((ams - exact).abs() < exact);
+    }
+
+    #[test]
+    fn higher_moments() {
+        let mut fm = FrequencyMoment::<u32>::new();
+        staircase(20).for_each(|v| fm.process(v));
+        assert_eq!(fm.query(&1), 210.0);
+        for k in [2, 3, 4] {
+            let exact = (1..=20u32).map(|i| (i as f64).powi(k as i32)).sum::<f64>();
+            let e = fm.estimate(&k, 0.95);
+            assert!(e.contains(exact), "F{}: {:?} {}", k, e, exact);
+        }
+    }
+
+    #[test]
+    fn entropy() {
+        // 8 equally likely items, then one item taking half of the stream
+        let uniform = Entropy::<u32>::apply((0..8000).map(|i| i % 8), &());
+        assert!((uniform - 3.0).abs() < 0.2, "{}", uniform);
+        let skewed = (0..8000).map(|i| if i % 2 == 0 { 0 } else { i % 8 });
+        let mut h = Entropy::<u32>::new();
+        skewed.for_each(|v| h.process(v));
+        let exact = 0.5 + 4.0 * 0.125 * 3.0;
+        let e = h.estimate(&(), 0.95);
+        assert!(e.contains(exact), "{:?} {}", e, exact);
+        assert!((e.value - exact).abs() < 0.2, "{:?}", e);
+    }
+
+    #[test]
+    fn join_size() {
+        let empty = Ams::<u32, PolynomialHash<4>, 64, 7>::new();
+        let (mut a, mut b) = (empty.clone(), empty);
+        staircase(30).for_each(|v| a.process(v));
+        (1..=30).for_each(|v| b.process(v % 10));
+        // b holds each of 0..10 three times, and a holds i of each i
+        let exact = (1..10).map(|i| 3 * i).sum::<u32>();
+        let e = a.estimate_inner_product(&b, 0.99);
+        assert!(e.contains(exact as f64), "{:?} {}", e, exact);
+    }
+
+    #[test]
+    fn merge_and_batch_are_exact() {
+        let empty = Ams::<u32>::new();
+        let (mut all, mut a, mut b) = (empty.clone(), empty.clone(), empty);
+        let items = staircase(15).collect::<Vec<_>>();
+        all.process_batch(&items);
+        let (left, right) = items.split_at(40);
+        left.iter().for_each(|&v| a.process(v));
+        right.iter().for_each(|&v| b.process(v));
+        a.merge(b);
+        assert_eq!(a, all);
+    }
+}
@@ -0,0 +1,159 @@
+use super::{Merge, StreamProcessor};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+/// Number of items sent to a worker at once by `apply_iter`.
+const CHUNK: usize = 4096;
+
+/// Splits a slice into `threads` contiguous parts, and processes each on its own thread with a
+/// clone of one new processor. The processors are then merged and queried.
+/// A good number of threads is `std::thread::available_parallelism`.
+pub fn apply_slice<'a, T: Sync, P>(items: &'a [T], threads: usize, args: &P::Args) -> P::Result
+where
+    P: StreamProcessor<&'a T> + Merge + Clone + Send,
+{
+    assert_ne!(threads, 0);
+    let proto = P::new();
+    let part_len = items.len().div_ceil(threads).max(1);
+    let parts = thread::scope(|s| {
+        let workers = items
+            .chunks(part_len)
+            .map(|part| {
+                let mut p = proto.clone();
+                s.spawn(move || {
+                    for t in part {
+                        p.process(t);
+                    }
+                    p
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    merge_all(proto, parts).query(args)
+}
+
+/// Reads an iterator on the calling thread, and hands chunks of it out to `threads` workers
+/// which each process them with a clone of one new processor. The processors are then merged
+/// and queried.
+pub fn apply_iter<I, P>(iter: I, threads: usize, args: &P::Args) -> P::Result
+where
+    I: Iterator,
+    I::Item: Send,
+    P: StreamProcessor<I::Item> + Merge + Clone + Send,
+{
+    assert_ne!(threads, 0);
+    let proto = P::new();
+    let parts = thread::scope(|s| {
+        let (senders, workers): (Vec<_>, Vec<_>) = (0..threads)
+            .map(|_| {
+                let (send, recv) = sync_channel::<Vec<I::Item>>(2);
+                let mut p = proto.clone();
+                let worker = s.spawn(move || {
+                    for chunk in recv {
+                        for t in chunk {
+                            p.process(t);
+                        }
+                    }
+                    p
+                });
+                (send, worker)
+            })
+            .unzip();
+
+        let mut iter = iter.peekable();
+        for send in senders.iter().cycle() {
+            if iter.peek().is_none() {
+                break;
+            }
+            let chunk = iter.by_ref().take(CHUNK).collect();
+            send.send(chunk).unwrap();
+        }
+        drop(senders);
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    merge_all(proto, parts).query(args)
+}
+
+fn merge_all<P: Merge>(mut acc: P, parts: Vec<P>) -> P {
+    for p in parts {
+        acc.merge(p);
+    }
+    acc
+}
+
+#[cfg(test)]
+mod test_parallel {
+    use crate::adapters::MedianOfMeans;
+    use crate::count::{ExactCounter, MorrisCounter};
+    use crate::distinct::HyperLogLog;
+    use crate::high_freq::MisraGries;
+
+    #[test]
+    fn exact_count() {
+        let items = (0..10_001).collect::<Vec<u32>>();
+        for threads in 1..5 {
+            assert_eq!(
+                super::apply_slice::<_, ExactCounter>(&items, threads, &()),
+                10_001
+            );
+            assert_eq!(
+                super::apply_iter::<_, ExactCounter>(0..10_001, threads, &()),
+                10_001
+            );
+        }
+        assert_eq!(super::apply_slice::<u32, ExactCounter>(&[], 3, &()), 0);
+    }
+
+    #[test]
+    fn distinct() {
+        let n = 100_000;
+        let hll = super::apply_iter::<_, HyperLogLog<u32>>((0..n).map(|i| i % 50_000), 4, &());
+        assert!(hll.abs_diff(50_000) < 3_500, "{}", hll);
+    }
+
+    #[test]
+    fn majority() {
+        let items = (0..9_000)
+            .map(|i| if i % 3 == 0 { i } else { 7 })
+            .collect::<Vec<u32>>();
+        let maj = super::apply_slice::<_, MisraGries<&u32, 1>>(&items, 4, &());
+        assert_eq!(maj, vec![&7]);
+    }
+
+    /// Standard deviation of `runs` parallel estimates of the length of a stream of `n` items
+    /// split over 4 shards.
+    fn spread<P>(n: usize, runs: usize) -> f64
+    where
+        P: for<'a> crate::StreamProcessor<&'a u32, Result = usize, Args = ()>
+            + crate::Merge
+            + Clone
+            + Send,
+    {
+        let items = vec![0u32; n];
+        let estimates = (0..runs)
+            .map(|_| super::apply_slice::<_, P>(&items, 4, &()) as f64)
+            .collect::<Vec<_>>();
+        let mean = estimates.iter().sum::<f64>() / runs as f64;
+        let var = estimates.iter().map(|e| (e - mean).powi(2)).sum::<f64>() / runs as f64;
+        var.sqrt()
+    }
+
+    #[test]
+    fn independent_shards() {
+        // Clones of one counter must not share random choices, or the merged count varies more
+        // than a single counter over the whole stream, whose standard deviation is about 2828.
+        let n = 4_000;
+        let single = (n as f64 * (n - 1) as f64 / 2.0).sqrt();
+        let morris = spread::<MorrisCounter>(n, 1000);
+        assert!(morris < 1.25 * single, "{}", morris);
+        let mom = spread::<MedianOfMeans<MorrisCounter, 4, 3>>(n, 1000);
+        assert!(mom < 0.36 * single, "{}", mom);
+    }
+}
@@ -1,4 +1,5 @@
-use super::{rand, StreamProcessor};
+use super::estimate::check_confidence;
+use super::{rand, Estimate, Estimator, Merge, StreamProcessor};
 use crate::count::ExactCounter;
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -14,7 +15,8 @@ pub struct Quantile<
 }
 
 impl<T, const E: usize, const K: usize, C> Quantile<T, E, K, C> {
-    const CHANCE: f32 = (E as f32 / K as f32);
+    /// Probability of keeping each element, so that about K of E elements are sampled.
+    const CHANCE: f32 = (K as f32 / E as f32);
 }
 
 impl<T: Ord, const E: usize, const K: usize, C: StreamProcessor<(), Args = (), Result = usize>>
@@ -31,10 +33,10 @@ impl<T: Ord, const E: usize, const K: usize, C: StreamProcessor<(), Args = (), R
         if rand() > Self::CHANCE {
             return;
         }
-        let idx = self.samples.binary_search(&t);
-        if let Err(idx) = idx {
-            self.samples.insert(idx, t);
-        }
+        let idx = match self.samples.binary_search(&t) {
+            Ok(i) | Err(i) => i,
+        };
+        self.samples.insert(idx, t);
     }
     type Result = usize;
     type Args = T;
@@ -50,3 +52,40 @@ impl<T: Ord, const E: usize, const K: usize, C: StreamProcessor<(), Args = (), R
         (count * i / sampled) as usize
     }
 }
+
+impl<T: Ord, const E: usize, const K: usize, C: StreamProcessor<(), Args = (), Result = usize>>
+    Estimator<T> for Quantile<T, E, K, C>
+{
+    /// Bounds the rank with the Dvoretzky-Kiefer-Wolfowitz inequality, since the kept elements
+    /// are a uniform sample of the stream. Assumes that the counter is exact.
+    fn estimate(&self, a: &T, confidence: f64) -> Estimate {
+        check_confidence(confidence);
+        let count = self.counter.query(&()) as f64;
+        let value = self.query(a) as f64;
+        if self.samples.is_empty() {
+            return Estimate {
+                value,
+                lower: 0.0,
+                upper: count,
+                confidence,
+            };
+        }
+        let k = self.samples.len() as f64;
+        let eps = ((2.0 / (1.0 - confidence)).ln() / (2.0 * k)).sqrt();
+        Estimate {
+            value,
+            lower: (value - eps * count).max(0.0),
+            upper: (value + eps * count).min(count),
+            confidence,
+        }
+    }
+}
+
+impl<T: Ord, const E: usize, const K: usize, C: Merge> Merge for Quantile<T, E, K, C> {
+    /// Both sample at the same rate, so the union of samples is a sample of both streams.
+    fn merge(&mut self, other: Self) {
+        self.counter.merge(other.counter);
+        self.samples.extend(other.samples);
+        self.samples.sort();
+    }
+}
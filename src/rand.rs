@@ -1,7 +1,50 @@
-static mut SEED: f32 = 13.37;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Returns a uniformly random number in `[0, 1)`.
 pub fn rand() -> f32 {
-    unsafe {
-        SEED = SEED * 9473f32 + 13f32;
-        (SEED.sin() + 1.0) / 2.0
+    to_unit(seed())
+}
+
+/// Maps the top 24 bits of a random word to `[0, 1)`.
+fn to_unit(v: u64) -> f32 {
+    (v >> 40) as f32 / (1u64 << 24) as f32
+}
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+static SEEDS: AtomicU64 = AtomicU64::new(0x1337);
+
+/// Returns a fresh 64 bit seed, distinct from every seed returned before it.
+/// Used to draw independent hash functions for each new sketch.
+pub fn seed() -> u64 {
+    let state = SEEDS.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed);
+    SplitMix64::mix(state.wrapping_add(GOLDEN_GAMMA))
+}
+
+/// Small generator used to expand one seed into many random words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+    pub fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(GOLDEN_GAMMA);
+        Self::mix(self.0)
+    }
+    /// Returns a uniformly random number in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        to_unit(self.next_u64())
+    }
+    /// Returns a uniformly random number in `[0, 1)` with 53 random bits, for when the 24 of
+    /// `next_f32` are too coarse.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * 2f64.powi(-53)
     }
 }
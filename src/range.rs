@@ -0,0 +1,264 @@
+use super::estimate::check_confidence;
+use super::hash::CarterWegman;
+use super::high_freq::{count_min_dimensions, count_min_error, DynCountMin};
+use super::{Estimate, Estimator, HashFunction, Merge, StreamProcessor};
+
+/// Counts of the nodes of one level of the dyadic tree.
+#[derive(Debug, Clone, PartialEq)]
+enum Level<S> {
+    /// Levels with few nodes are counted exactly, in less space than a sketch.
+    Exact(Vec<u32>),
+    Sketch(DynCountMin<u64, S>),
+}
+
+/// Counts of integers in `[0, 2^L)` over the dyadic intervals of each length, each level kept in
+/// a Count-Min sketch. The count of any range `[a, b]` is the sum of at most `2L` such intervals,
+/// so it takes `O(L)` point queries, and quantiles are found by descending the tree.
+///
+/// Estimates never undercount, and the levels near the root are counted exactly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DyadicCountMin<const L: u32, S = CarterWegman> {
+    /// Level `j` counts the prefixes `x >> j`, for `j` below `L`.
+    levels: Vec<Level<S>>,
+    total: u64,
+}
+
+impl<const L: u32, S: HashFunction<u64>> DyadicCountMin<L, S> {
+    /// Sizes the sketch of every level like `DynCountMin::with_error`, so that each point query
+    /// overestimates by at most `epsilon` times the length of the stream with probability at
+    /// least `1 - delta`. Range counts may add up the error of `2L` point queries.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        let (width, depth) = count_min_dimensions(epsilon, delta);
+        Self::with_dimensions(width, depth)
+    }
+    /// Creates a sketch whose levels have `depth` rows of `width` counters each.
+    pub fn with_dimensions(width: usize, depth: usize) -> Self {
+        assert!(L != 0 && L <= 64);
+        let cells = width * depth;
+        let levels = (0..L)
+            .map(|j| {
+                let nodes_log2 = L - j;
+                if nodes_log2 < usize::BITS && 1 << nodes_log2 <= cells {
+                    Level::Exact(vec![0; 1 << nodes_log2])
+                } else {
+                    Level::Sketch(DynCountMin::with_dimensions(width, depth))
+                }
+            })
+            .collect();
+        Self { levels, total: 0 }
+    }
+    /// Number of elements seen.
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+    /// Estimated count of the `prefix`th interval of length `2^level`.
+    fn node(&self, level: u32, prefix: u64) -> usize {
+        match self.levels.get(level as usize) {
+            None => self.total as usize,
+            Some(Level::Exact(counts)) => counts[prefix as usize] as usize,
+            Some(Level::Sketch(cm)) => cm.query(&prefix),
+        }
+    }
+    /// Splits `[a, b]` into maximal dyadic intervals, as `(level, prefix)` pairs.
+    fn intervals(a: u64, b: u64) -> Vec<(u32, u64)> {
+        assert!(a <= b);
+        Self::check(b);
+        let mut nodes = vec![];
+        // Half open, so that the end of the universe fits.
+        let (mut lo, mut hi) = (u128::from(a), u128::from(b) + 1);
+        for level in 0..=L {
+            if lo >= hi {
+                break;
+            }
+            if lo & 1 == 1 {
+                nodes.push((level, lo as u64));
+                lo += 1;
+            }
+            if hi & 1 == 1 {
+                hi -= 1;
+                nodes.push((level, hi as u64));
+            }
+            lo >>= 1;
+            hi >>= 1;
+        }
+        nodes
+    }
+    fn check(x: u64) {
+        assert!(L == 64 || x >> L == 0, "{} is outside of [0, 2^{})", x, L);
+    }
+    /// Estimates the smallest value whose rank reaches `q` times the length of the stream, by
+    /// descending from the root towards the leftmost interval which holds that many elements.
+    /// As counts never undercount, the result is never above the true quantile. Returns `None`
+    /// for an empty stream.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        assert!((0.0..=1.0).contains(&q));
+        if self.is_empty() {
+            return None;
+        }
+        let mut rank = ((q * self.total as f64).ceil() as usize).max(1);
+        let mut prefix = 0;
+        for level in (0..L).rev() {
+            let left = prefix << 1;
+            let count = self.node(level, left);
+            if rank <= count {
+                prefix = left;
+            } else {
+                rank -= count;
+                prefix = left | 1;
+            }
+        }
+        Some(prefix)
+    }
+}
+
+impl<const L: u32, S: HashFunction<u64>> StreamProcessor<u64> for DyadicCountMin<L, S> {
+    /// Creates a sketch with `epsilon = 0.001` and `delta = 0.01`.
+    fn new() -> Self {
+        Self::with_error(0.001, 0.01)
+    }
+    fn process(&mut self, x: u64) {
+        Self::check(x);
+        self.total += 1;
+        for (j, level) in self.levels.iter_mut().enumerate() {
+            match level {
+                Level::Exact(counts) => counts[(x >> j) as usize] += 1,
+                Level::Sketch(cm) => cm.process(x >> j),
+            }
+        }
+    }
+    /// Batches the updates of every sketched level.
+    fn process_batch(&mut self, xs: &[u64]) {
+        xs.iter().copied().for_each(Self::check);
+        self.total += xs.len() as u64;
+        let mut prefixes = xs.to_vec();
+        for level in self.levels.iter_mut() {
+            match level {
+                Level::Exact(counts) => prefixes.iter().for_each(|&p| counts[p as usize] += 1),
+                Level::Sketch(cm) => cm.process_batch(&prefixes),
+            }
+            prefixes.iter_mut().for_each(|p| *p >>= 1);
+        }
+    }
+    type Result = usize;
+    /// Inclusive range `[a, b]`.
+    type Args = (u64, u64);
+    /// Estimates how many elements lie in `[a, b]`.
+    fn query(&self, &(a, b): &(u64, u64)) -> usize {
+        Self::intervals(a, b)
+            .into_iter()
+            .map(|(level, prefix)| self.node(level, prefix))
+            .sum()
+    }
+}
+
+impl<const L: u32, S: HashFunction<u64>> Estimator<u64> for DyadicCountMin<L, S> {
+    /// Adds up the errors of the sketched intervals, each bounded at a confidence which makes
+    /// them all hold together by the union bound.
+    fn estimate(&self, range: &(u64, u64), confidence: f64) -> Estimate {
+        check_confidence(confidence);
+        let value = self.query(range) as f64;
+        let sketched = Self::intervals(range.0, range.1)
+            .into_iter()
+            .filter_map(|(level, _)| match self.levels.get(level as usize) {
+                Some(Level::Sketch(cm)) => Some(cm),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if sketched.is_empty() {
+            return Estimate::exact(value);
+        }
+        let each = 1.0 - (1.0 - confidence) / sketched.len() as f64;
+        let total = self.total as f64;
+        let err = sketched
+            .iter()
+            .map(|cm| count_min_error(total, cm.width(), cm.depth(), each))
+            .sum::<f64>();
+        Estimate {
+            value,
+            lower: (value - err).max(0.0),
+            upper: value.min(total),
+            confidence,
+        }
+    }
+}
+
+impl<const L: u32, S> Merge for DyadicCountMin<L, S> {
+    fn merge(&mut self, other: Self) {
+        self.total += other.total;
+        for (a, b) in self.levels.iter_mut().zip(other.levels) {
+            match (a, b) {
+                (Level::Exact(a), Level::Exact(b)) => {
+                    a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                }
+                (Level::Sketch(a), Level::Sketch(b)) => a.merge(b),
+                _ => panic!("merged sketches of different dimensions"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_range {
+    use super::DyadicCountMin;
+    use crate::{Estimator, Merge, StreamProcessor};
+
+    #[test]
+    fn splits_into_dyadic_intervals() {
+        type D = DyadicCountMin<4>;
+        assert_eq!(D::intervals(0, 15), [(4, 0)]);
+        assert_eq!(D::intervals(3, 3), [(0, 3)]);
+        // [1, 14] = 1 + [2, 3] + [4, 7] + [8, 11] + [12, 13] + 14
+        let mut nodes = D::intervals(1, 14);
+        nodes.sort_unstable();
+        assert_eq!(nodes, [(0, 1), (0, 14), (1, 1), (1, 6), (2, 1), (2, 2)]);
+        assert_eq!(DyadicCountMin::<64>::intervals(0, u64::MAX), [(64, 0)]);
+    }
+
+    #[test]
+    fn ranges_and_quantiles() {
+        let mut d = DyadicCountMin::<32>::with_error(0.001, 0.01);
+        let xs = (0..10_000u64)
+            .map(|i| i * 7919 % 10_000 * 1000)
+            .collect::<Vec<_>>();
+        d.process_batch(&xs);
+        assert_eq!(d.len(), 10_000);
+        let exact = (1000..=5_000_000).step_by(1000).count();
+        let est = d.estimate(&(1000, 5_000_000), 0.99);
+        assert!(est.contains(exact as f64));
+        assert!(est.upper - est.lower < 1000.0);
+        let median = d.quantile(0.5).unwrap();
+        assert!(median <= 4_999_000 && median > 4_500_000);
+        let max = d.quantile(1.0).unwrap();
+        assert!(max <= 9_999_000 && max > 9_900_000);
+    }
+
+    #[test]
+    fn empty() {
+        let d = DyadicCountMin::<8>::new();
+        assert_eq!(d.quantile(0.5), None);
+        assert_eq!(d.query(&(0, 255)), 0);
+        assert_eq!(d.estimate(&(0, 255), 0.99).upper, 0.0);
+    }
+
+    quickcheck! {
+      fn counts_never_undercount(xs: Vec<u16>, a: u16, b: u16) -> bool {
+          let (a, b) = (a.min(b) as u64, a.max(b) as u64);
+          let mut d = DyadicCountMin::<16>::with_dimensions(8, 2);
+          let half = xs.len() / 2;
+          let mut other = d.clone();
+          xs[..half].iter().for_each(|&x| d.process(x.into()));
+          xs[half..].iter().for_each(|&x| other.process(x.into()));
+          d.merge(other);
+          let exact = xs.iter().filter(|&&x| (a..=b).contains(&x.into())).count();
+          let below = |q: u64| xs.iter().filter(|&&x| u64::from(x) < q).count();
+          let median_ok = match d.quantile(0.5) {
+              Some(q) => below(q) * 2 < xs.len(),
+              None => xs.is_empty(),
+          };
+          d.query(&(a, b)) >= exact && median_ok
+      }
+    }
+}
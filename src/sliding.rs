@@ -0,0 +1,458 @@
+use super::estimate::check_confidence;
+use super::high_freq::MisraGries;
+use super::window::{Events, Timestamps};
+use super::{Estimate, Estimator, StreamProcessor};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Exponential histogram of Datar, Gionis, Indyk and Motwani, which sums over a sliding window
+/// by grouping the units of the sum into buckets whose sizes are powers of two.
+///
+/// Only the oldest bucket can be partly outside the window, which gives the error. Keeping at
+/// least `ceil(k / 2)` buckets of each smaller size means that its half is at most a `1 / k`
+/// fraction of the sum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ExpHistogram {
+    /// Most buckets kept of each size.
+    max: u64,
+    /// Buckets of size `2^i` as `(timestamp, multiplicity)` pairs, oldest first. Every bucket in
+    /// `levels[i + 1]` is older than those in `levels[i]`.
+    levels: Vec<VecDeque<(u64, u64)>>,
+    /// Number of buckets of each size.
+    counts: Vec<u64>,
+    now: u64,
+}
+
+impl ExpHistogram {
+    fn with_error(eps: f64) -> Self {
+        assert!(0.0 < eps && eps < 1.0, "error must be in (0, 1)");
+        let k = eps.recip().ceil() as u64;
+        Self {
+            max: k.div_ceil(2) + 1,
+            levels: vec![VecDeque::new()],
+            counts: vec![0],
+            now: 0,
+        }
+    }
+    /// Adds `v` units at time `t`, which is moved up to the latest time seen so far.
+    fn insert(&mut self, t: u64, v: u64, window: u64) {
+        self.now = self.now.max(t);
+        self.expire(window);
+        if v == 0 {
+            return;
+        }
+        Self::push(&mut self.levels[0], self.now, v);
+        self.counts[0] += v;
+        self.cascade();
+    }
+    fn push(level: &mut VecDeque<(u64, u64)>, t: u64, n: u64) {
+        match level.back_mut() {
+            Some((last, m)) if *last == t => *m += n,
+            _ => level.push_back((t, n)),
+        }
+    }
+    /// Merges the oldest pairs of each size with too many buckets into the next size, keeping
+    /// the timestamp of the newer bucket in each pair.
+    fn cascade(&mut self) {
+        let mut i = 0;
+        while self.counts[i] > self.max {
+            let mut merges = (self.counts[i] - self.max).div_ceil(2);
+            self.counts[i] -= 2 * merges;
+            if i + 1 == self.levels.len() {
+                self.levels.push(VecDeque::new());
+                self.counts.push(0);
+            }
+            let (lo, hi) = self.levels.split_at_mut(i + 1);
+            let (level, next) = (&mut lo[i], &mut hi[0]);
+            while merges > 0 {
+                let (t, n) = level.front_mut().unwrap();
+                if *n >= 2 {
+                    let pairs = (*n / 2).min(merges);
+                    *n -= 2 * pairs;
+                    merges -= pairs;
+                    Self::push(next, *t, pairs);
+                    self.counts[i + 1] += pairs;
+                    if *n == 0 {
+                        level.pop_front();
+                    }
+                } else {
+                    level.pop_front();
+                    let (t, n) = level.front_mut().unwrap();
+                    *n -= 1;
+                    merges -= 1;
+                    Self::push(next, *t, 1);
+                    self.counts[i + 1] += 1;
+                    if *n == 0 {
+                        level.pop_front();
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+    /// Drops buckets whose latest unit is more than `window` before now.
+    fn expire(&mut self, window: u64) {
+        for i in (0..self.levels.len()).rev() {
+            let level = &mut self.levels[i];
+            while let Some(&(t, n)) = level.front() {
+                if t + window > self.now {
+                    return;
+                }
+                level.pop_front();
+                self.counts[i] -= n;
+            }
+        }
+    }
+    /// Bounds the sum of the last `window` time units, as `(lower, upper)`.
+    fn bounds(&self, window: u64) -> (u64, u64) {
+        let mut total = 0;
+        let mut oldest = 0;
+        for (i, level) in self.levels.iter().enumerate() {
+            for &(t, n) in level {
+                if t + window > self.now {
+                    total += n << i;
+                    oldest = 1 << i;
+                }
+            }
+        }
+        if total == 0 {
+            return (0, 0);
+        }
+        (total - oldest + 1, total)
+    }
+}
+
+/// Counts the `true` elements among the last `W` events, or within the last `W` time units for
+/// streams of `(timestamp, bool)` pairs with non-decreasing timestamps. The count is within a
+/// relative error `eps` of the truth, using `O(log(W) / eps)` buckets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlidingCount<const W: u64, C = Events> {
+    clock: PhantomData<C>,
+    hist: ExpHistogram,
+}
+
+/// Sums the non-negative integers among the last `W` events, or within the last `W` time units
+/// for streams of `(timestamp, value)` pairs with non-decreasing timestamps. The sum is within a
+/// relative error `eps` of the truth, with each value added in `O(log(value))` time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlidingSum<const W: u64, C = Events> {
+    clock: PhantomData<C>,
+    hist: ExpHistogram,
+}
+
+macro_rules! impl_sliding {
+    ($name:ident, $result:ty) => {
+        impl<const W: u64, C> $name<W, C> {
+            /// Creates a processor with a relative error of at most `eps`.
+            pub fn with_error(eps: f64) -> Self {
+                assert_ne!(W, 0);
+                Self {
+                    clock: PhantomData,
+                    hist: ExpHistogram::with_error(eps),
+                }
+            }
+            fn query_window(&self) -> $result {
+                let (lower, upper) = self.hist.bounds(W);
+                (upper - (upper - lower).div_ceil(2)) as $result
+            }
+            fn estimate_window(&self, confidence: f64) -> Estimate {
+                check_confidence(confidence);
+                let (lower, upper) = self.hist.bounds(W);
+                Estimate {
+                    value: self.query_window() as f64,
+                    lower: lower as f64,
+                    upper: upper as f64,
+                    confidence,
+                }
+            }
+        }
+    };
+}
+
+impl_sliding!(SlidingCount, usize);
+impl_sliding!(SlidingSum, u64);
+
+/// Implements `StreamProcessor` and `Estimator` for one clock, given how it reads the time and
+/// the amount to add from each element.
+macro_rules! impl_sliding_clock {
+    ($name:ident, $clock:ty, $elem:ty, $result:ty, |$s:ident, $v:pat_param| $time_amount:expr) => {
+        impl<const W: u64> StreamProcessor<$elem> for $name<W, $clock> {
+            /// Uses a relative error of 1%.
+            fn new() -> Self {
+                Self::with_error(0.01)
+            }
+            fn process(&mut $s, $v: $elem) {
+                let (t, amount) = $time_amount;
+                $s.hist.insert(t, amount, W);
+            }
+            type Result = $result;
+            type Args = ();
+            fn query(&self, (): &()) -> $result {
+                self.query_window()
+            }
+        }
+
+        impl<const W: u64> Estimator<$elem> for $name<W, $clock> {
+            /// The bounds hold with certainty.
+            fn estimate(&self, (): &(), confidence: f64) -> Estimate {
+                self.estimate_window(confidence)
+            }
+        }
+    };
+}
+
+impl_sliding_clock!(SlidingCount, Events, bool, usize, |self, v| (
+    self.hist.now + 1,
+    v as u64
+));
+impl_sliding_clock!(
+    SlidingCount,
+    Timestamps,
+    (u64, bool),
+    usize,
+    |self, (t, v)| (t, v as u64)
+);
+impl_sliding_clock!(SlidingSum, Events, u64, u64, |self, v| (
+    self.hist.now + 1,
+    v
+));
+impl_sliding_clock!(SlidingSum, Timestamps, (u64, u64), u64, |self, (t, v)| (
+    t, v
+));
+
+/// Finds the most frequent items among the last `W` events, or within the last `W` time units
+/// for streams of `(timestamp, item)` pairs with non-decreasing timestamps.
+///
+/// The window is split into `PANES` panes, each summarized by a `MisraGries` with `K`
+/// counters, and the oldest pane is dropped whenever a new one starts. Results cover the open
+/// pane and the `PANES - 1` before it, so the window ends at a pane boundary and may reach up to
+/// one pane less far back than `W`. The frequency of an item in the kept panes is undercounted by
+/// at most `n / (K + 1)`, where `n` is the number of elements in them.
+#[derive(Clone, Debug)]
+pub struct SlidingHeavyHitters<T, const K: usize, const W: u64, C = Events, const PANES: usize = 8>
+{
+    clock: PhantomData<C>,
+    /// Panes as `(index, elements, summary)`, oldest first.
+    panes: VecDeque<(u64, usize, MisraGries<T, K>)>,
+    /// Position of the next element, when measuring in events.
+    next: u64,
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const W: u64, C, const PANES: usize>
+    SlidingHeavyHitters<T, K, W, C, PANES>
+{
+    const PANE: u64 = W / PANES as u64;
+    fn empty() -> Self {
+        assert_ne!(PANES, 0);
+        assert!(
+            W % PANES as u64 == 0 && W > 0,
+            "window must be a positive multiple of the number of panes"
+        );
+        Self {
+            clock: PhantomData,
+            panes: VecDeque::with_capacity(PANES),
+            next: 0,
+        }
+    }
+    fn insert(&mut self, t: u64, v: T) {
+        let pane = t / Self::PANE;
+        match self.panes.back() {
+            Some(&(last, _, _)) if last >= pane => {}
+            _ => {
+                while self
+                    .panes
+                    .front()
+                    .is_some_and(|&(first, _, _)| first + PANES as u64 <= pane)
+                {
+                    self.panes.pop_front();
+                }
+                self.panes.push_back((pane, 0, MisraGries::new()));
+            }
+        }
+        let (_, n, summary) = self.panes.back_mut().unwrap();
+        *n += 1;
+        summary.process(v);
+    }
+    /// Number of elements in the kept panes.
+    pub fn len(&self) -> usize {
+        self.panes.iter().map(|(_, n, _)| n).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.panes.is_empty()
+    }
+    /// Lower bound on the frequency of `v` in the window.
+    pub fn frequency(&self, v: &T) -> usize {
+        let counts = self.panes.iter().map(|(_, _, s)| s.counts.get(v));
+        counts.flatten().sum()
+    }
+    /// Returns up to `k` of the most frequent items with their frequencies, most frequent first.
+    pub fn top(&self, k: usize) -> Vec<(T, usize)> {
+        let mut totals = HashMap::new();
+        for (_, _, summary) in &self.panes {
+            for (v, c) in &summary.counts {
+                *totals.entry(v).or_insert(0) += c;
+            }
+        }
+        let mut top = totals
+            .into_iter()
+            .map(|(v, c)| (v.clone(), c))
+            .collect::<Vec<_>>();
+        top.sort_unstable_by_key(|&(_, c)| Reverse(c));
+        top.truncate(k);
+        top
+    }
+    fn frequency_bounds(&self, v: &T, confidence: f64) -> Estimate {
+        check_confidence(confidence);
+        let value = self.frequency(v) as f64;
+        Estimate {
+            value,
+            lower: value,
+            upper: value + (self.len() / (K + 1)) as f64,
+            confidence,
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const W: u64, const PANES: usize> StreamProcessor<T>
+    for SlidingHeavyHitters<T, K, W, Events, PANES>
+{
+    fn new() -> Self {
+        Self::empty()
+    }
+    fn process(&mut self, v: T) {
+        self.insert(self.next, v);
+        self.next += 1;
+    }
+    type Result = usize;
+    type Args = T;
+    /// Returns a lower bound on the frequency of an item.
+    fn query(&self, v: &T) -> usize {
+        self.frequency(v)
+    }
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const W: u64, const PANES: usize>
+    StreamProcessor<(u64, T)> for SlidingHeavyHitters<T, K, W, Timestamps, PANES>
+{
+    fn new() -> Self {
+        Self::empty()
+    }
+    fn process(&mut self, (t, v): (u64, T)) {
+        self.insert(t, v);
+    }
+    type Result = usize;
+    type Args = T;
+    /// Returns a lower bound on the frequency of an item.
+    fn query(&self, v: &T) -> usize {
+        self.frequency(v)
+    }
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const W: u64, const PANES: usize> Estimator<T>
+    for SlidingHeavyHitters<T, K, W, Events, PANES>
+{
+    /// The bounds hold with certainty.
+    fn estimate(&self, v: &T, confidence: f64) -> Estimate {
+        self.frequency_bounds(v, confidence)
+    }
+}
+
+impl<T: Hash + Eq + Clone, const K: usize, const W: u64, const PANES: usize> Estimator<(u64, T)>
+    for SlidingHeavyHitters<T, K, W, Timestamps, PANES>
+{
+    /// The bounds hold with certainty.
+    fn estimate(&self, v: &T, confidence: f64) -> Estimate {
+        self.frequency_bounds(v, confidence)
+    }
+}
+
+#[cfg(test)]
+mod test_sliding {
+    use super::{SlidingCount, SlidingHeavyHitters, SlidingSum};
+    use crate::window::Timestamps;
+    use crate::{Estimator, StreamProcessor};
+
+    #[test]
+    fn count_last_events() {
+        let mut c = SlidingCount::<1000>::with_error(0.1);
+        for i in 0..10_000 {
+            c.process(i % 3 == 0);
+        }
+        let exact = (9000..10_000).filter(|i| i % 3 == 0).count();
+        let approx = c.query(&());
+        assert!(approx.abs_diff(exact) as f64 <= 0.1 * exact as f64);
+        assert!(c.estimate(&(), 0.5).contains(exact as f64));
+        // only O(log(W) / eps) buckets are kept
+        assert!(c.hist.counts.iter().sum::<u64>() < 100);
+    }
+
+    #[test]
+    fn sum_last_seconds() {
+        let mut s = SlidingSum::<60, Timestamps>::new();
+        for t in 0..600 {
+            s.process((t, t % 7));
+        }
+        let exact = (540..600).map(|t| t % 7).sum::<u64>();
+        let e = s.estimate(&(), 0.99);
+        assert!(e.contains(exact as f64), "{:?} {}", e, exact);
+        assert!((e.value - exact as f64).abs() <= 0.01 * exact as f64);
+        // nothing left after a long pause
+        s.process((1000, 0));
+        assert_eq!(s.query(&()), 0);
+    }
+
+    #[test]
+    fn heavy_hitters_forget() {
+        let mut hh = SlidingHeavyHitters::<u32, 4, 160, Timestamps>::new();
+        // 7 is hot at first, then 9 takes over
+        for t in 0..1000 {
+            let v = match t % 4 {
+                0 | 1 => (t < 500) as u32 * 7 + (t >= 500) as u32 * 9,
+                _ => t as u32,
+            };
+            hh.process((t, v));
+        }
+        let top = hh.top(1);
+        assert_eq!(top[0].0, 9);
+        assert_eq!(hh.query(&7), 0);
+        // one timestamp per element, and half of them are 9
+        let e = hh.estimate(&9, 0.9);
+        assert!(e.contains((hh.len() / 2) as f64), "{:?}", e);
+        assert!((140..=160).contains(&hh.len()));
+    }
+
+    quickcheck! {
+      fn heavy_hitters_bounds(x: Vec<u8>) -> bool {
+          let mut hh = SlidingHeavyHitters::<u8, 3, 32, _, 4>::new();
+          for &v in &x {
+              hh.process(v);
+          }
+          // the kept panes hold the elements since the start of the oldest one
+          let kept = hh.len();
+          let exact = x[x.len() - kept..].iter().filter(|&&v| v == 0).count();
+          hh.estimate(&0, 0.5).contains(exact as f64) && kept <= 32
+      }
+      fn count_within_error(x: Vec<bool>) -> bool {
+          let mut c = SlidingCount::<16>::with_error(0.25);
+          for &b in &x {
+              c.process(b);
+          }
+          let exact = x.iter().rev().take(16).filter(|&&b| b).count();
+          let approx = c.query(&());
+          c.estimate(&(), 0.9).contains(exact as f64)
+              && approx.abs_diff(exact) as f64 <= 0.25 * exact as f64
+      }
+      fn sum_within_error(x: Vec<u8>) -> bool {
+          let mut s = SlidingSum::<16>::with_error(0.2);
+          for &v in &x {
+              s.process(v as u64);
+          }
+          let exact = x.iter().rev().take(16).map(|&v| v as u64).sum::<u64>();
+          let approx = s.query(&());
+          s.estimate(&(), 0.9).contains(exact as f64)
+              && approx.abs_diff(exact) as f64 <= 0.2 * exact as f64
+      }
+    }
+}
@@ -0,0 +1,297 @@
+use super::{Merge, StreamProcessor};
+
+/// Exact count, mean, variance, skewness, kurtosis, minimum and maximum of a stream of numbers.
+///
+/// Central moments are updated with the formulas of Welford and Terriberry, which stay accurate
+/// where summing powers would cancel catastrophically, and merged with those of Chan et al.
+/// Queries return a copy, whose methods give each statistic.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Moments {
+    n: u64,
+    mean: f64,
+    /// Sums of the second, third and fourth powers of the differences from the mean.
+    m2: f64,
+    m3: f64,
+    m4: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Moments {
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+    /// Mean of the stream, which is NaN if it is empty.
+    pub fn mean(&self) -> f64 {
+        if self.n == 0 {
+            return f64::NAN;
+        }
+        self.mean
+    }
+    /// Unbiased sample variance, which is NaN for fewer than two elements.
+    pub fn variance(&self) -> f64 {
+        if self.n < 2 {
+            return f64::NAN;
+        }
+        self.m2 / (self.n as f64 - 1.0)
+    }
+    /// Variance of the stream as a population.
+    pub fn population_variance(&self) -> f64 {
+        self.m2 / self.n as f64
+    }
+    /// Sample standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+    /// Skewness of the stream as a population.
+    pub fn skewness(&self) -> f64 {
+        (self.n as f64).sqrt() * self.m3 / self.m2.powf(1.5)
+    }
+    /// Excess kurtosis of the stream as a population, which is 0 for a normal distribution.
+    pub fn kurtosis(&self) -> f64 {
+        self.n as f64 * self.m4 / (self.m2 * self.m2) - 3.0
+    }
+    /// Smallest element, or infinity if the stream is empty.
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+    /// Largest element, or negative infinity if the stream is empty.
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+}
+
+impl StreamProcessor<f64> for Moments {
+    fn new() -> Self {
+        Self {
+            n: 0,
+            mean: 0.0,
+            m2: 0.0,
+            m3: 0.0,
+            m4: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+    fn process(&mut self, x: f64) {
+        let n1 = self.n as f64;
+        self.n += 1;
+        let n = self.n as f64;
+        let delta = x - self.mean;
+        let delta_n = delta / n;
+        let delta_n2 = delta_n * delta_n;
+        let term = delta * delta_n * n1;
+        self.mean += delta_n;
+        self.m4 += term * delta_n2 * (n * n - 3.0 * n + 3.0) + 6.0 * delta_n2 * self.m2
+            - 4.0 * delta_n * self.m3;
+        self.m3 += term * delta_n * (n - 2.0) - 3.0 * delta_n * self.m2;
+        self.m2 += term;
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+    }
+    type Result = Self;
+    type Args = ();
+    fn query(&self, (): &()) -> Self {
+        *self
+    }
+}
+
+impl Merge for Moments {
+    fn merge(&mut self, other: Self) {
+        if other.n == 0 {
+            return;
+        }
+        if self.n == 0 {
+            *self = other;
+            return;
+        }
+        let (na, nb) = (self.n as f64, other.n as f64);
+        let n = na + nb;
+        let delta = other.mean - self.mean;
+        let (d2, d3, d4) = (delta * delta, delta.powi(3), delta.powi(4));
+        let (a, b) = (*self, other);
+        self.n += other.n;
+        self.mean += delta * nb / n;
+        self.m2 = a.m2 + b.m2 + d2 * na * nb / n;
+        self.m3 = a.m3
+            + b.m3
+            + d3 * na * nb * (na - nb) / (n * n)
+            + 3.0 * delta * (na * b.m2 - nb * a.m2) / n;
+        self.m4 = a.m4
+            + b.m4
+            + d4 * na * nb * (na * na - na * nb + nb * nb) / (n * n * n)
+            + 6.0 * d2 * (na * na * b.m2 + nb * nb * a.m2) / (n * n)
+            + 4.0 * delta * (na * b.m3 - nb * a.m3) / n;
+        self.min = a.min.min(b.min);
+        self.max = a.max.max(b.max);
+    }
+}
+
+/// Exact covariance and correlation of a stream of `(x, y)` pairs, updated like `Moments`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Covariance {
+    n: u64,
+    mean_x: f64,
+    mean_y: f64,
+    /// Sums of squared differences from the means of `x` and `y`.
+    m2_x: f64,
+    m2_y: f64,
+    /// Sum of products of the differences from the means.
+    c: f64,
+}
+
+impl Covariance {
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+    /// Means of `x` and `y`.
+    pub fn means(&self) -> (f64, f64) {
+        if self.n == 0 {
+            return (f64::NAN, f64::NAN);
+        }
+        (self.mean_x, self.mean_y)
+    }
+    /// Unbiased sample covariance, which is NaN for fewer than two pairs.
+    pub fn covariance(&self) -> f64 {
+        if self.n < 2 {
+            return f64::NAN;
+        }
+        self.c / (self.n as f64 - 1.0)
+    }
+    /// Pearson correlation coefficient, between -1 and 1.
+    pub fn correlation(&self) -> f64 {
+        self.c / (self.m2_x * self.m2_y).sqrt()
+    }
+}
+
+impl StreamProcessor<(f64, f64)> for Covariance {
+    fn new() -> Self {
+        Self {
+            n: 0,
+            mean_x: 0.0,
+            mean_y: 0.0,
+            m2_x: 0.0,
+            m2_y: 0.0,
+            c: 0.0,
+        }
+    }
+    fn process(&mut self, (x, y): (f64, f64)) {
+        self.n += 1;
+        let n = self.n as f64;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += dx / n;
+        self.mean_y += dy / n;
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        self.c += dx * (y - self.mean_y);
+    }
+    type Result = Self;
+    type Args = ();
+    fn query(&self, (): &()) -> Self {
+        *self
+    }
+}
+
+impl Merge for Covariance {
+    fn merge(&mut self, other: Self) {
+        if other.n == 0 {
+            return;
+        }
+        if self.n == 0 {
+            *self = other;
+            return;
+        }
+        let (na, nb) = (self.n as f64, other.n as f64);
+        let n = na + nb;
+        let dx = other.mean_x - self.mean_x;
+        let dy = other.mean_y - self.mean_y;
+        self.n += other.n;
+        self.mean_x += dx * nb / n;
+        self.mean_y += dy * nb / n;
+        self.m2_x += other.m2_x + dx * dx * na * nb / n;
+        self.m2_y += other.m2_y + dy * dy * na * nb / n;
+        self.c += other.c + dx * dy * na * nb / n;
+    }
+}
+
+#[cfg(test)]
+mod test_stats {
+    use super::{Covariance, Moments};
+    use crate::{Merge, StreamProcessor};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-6 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    /// Computes the population central moments in two passes.
+    fn two_pass(xs: &[f64]) -> (f64, f64, f64, f64) {
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        let m = |k| xs.iter().map(|x| (x - mean).powi(k)).sum::<f64>() / n;
+        (mean, m(2), m(3), m(4))
+    }
+
+    #[test]
+    fn moments() {
+        let xs = (0..1000)
+            .map(|i| ((i * 37 % 101) as f64).powf(1.5) + 1e9)
+            .collect::<Vec<_>>();
+        let m = Moments::apply(xs.iter().copied(), &());
+        let (mean, m2, m3, m4) = two_pass(&xs);
+        assert_eq!(m.count(), 1000);
+        assert!(close(m.mean(), mean));
+        assert!(close(m.population_variance(), m2));
+        assert!(close(m.variance(), m2 * 1000.0 / 999.0));
+        assert!(close(m.skewness(), m3 / m2.powf(1.5)));
+        assert!(close(m.kurtosis(), m4 / (m2 * m2) - 3.0));
+        assert_eq!(m.min(), 1e9);
+        assert_eq!(m.max(), 100f64.powf(1.5) + 1e9);
+        let empty = Moments::new();
+        assert!(empty.mean().is_nan() && empty.variance().is_nan() && empty.std_dev().is_nan());
+        assert!(Moments::apply(vec![1.0].into_iter(), &())
+            .variance()
+            .is_nan());
+    }
+
+    #[test]
+    fn correlation() {
+        let pairs = (0..100).map(|i| (i as f64, 3.0 - 2.0 * i as f64));
+        let c = Covariance::apply(pairs, &());
+        assert!(close(c.correlation(), -1.0));
+        assert!(close(c.means().1, 3.0 - 99.0));
+        // covariance of 0..100 with itself is 100 * 101 / 12, scaled by -2
+        assert!(close(c.covariance(), -2.0 * 100.0 * 101.0 / 12.0));
+        assert!(Covariance::new().covariance().is_nan());
+    }
+
+    quickcheck! {
+      fn merged_moments(xs: Vec<i16>, split: usize) -> bool {
+          let xs = xs.into_iter().map(f64::from).collect::<Vec<_>>();
+          let split = split.checked_rem(xs.len() + 1).unwrap_or(0);
+          let all = Moments::apply(xs.iter().copied(), &());
+          let mut a = Moments::apply(xs[..split].iter().copied(), &());
+          a.merge(Moments::apply(xs[split..].iter().copied(), &()));
+          let same = |f: fn(&Moments) -> f64| {
+              let (x, y) = (f(&a), f(&all));
+              (x.is_nan() && y.is_nan()) || (x - y).abs() <= 1e-6 * x.abs().max(y.abs()).max(1.0)
+          };
+          a.count() == all.count()
+              && same(Moments::mean)
+              && same(Moments::variance)
+              && same(Moments::skewness)
+              && same(Moments::kurtosis)
+              && a.min() == all.min()
+              && a.max() == all.max()
+      }
+      fn merged_covariance(xs: Vec<(i16, i16)>, split: usize) -> bool {
+          let xs = xs.into_iter().map(|(x, y)| (x.into(), y.into())).collect::<Vec<_>>();
+          let split = split.checked_rem(xs.len() + 1).unwrap_or(0);
+          let all = Covariance::apply(xs.iter().copied(), &());
+          let mut a = Covariance::apply(xs[..split].iter().copied(), &());
+          a.merge(Covariance::apply(xs[split..].iter().copied(), &()));
+          let (x, y) = (a.covariance(), all.covariance());
+          (x.is_nan() && y.is_nan()) || (x - y).abs() <= 1e-6 * x.abs().max(y.abs()).max(1.0)
+      }
+    }
+}
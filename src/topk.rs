@@ -0,0 +1,233 @@
+use super::adapters::Project;
+use super::{Merge, StreamProcessor};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+
+/// Order in which `TopK` ranks keys, so that it keeps the first `K` of them.
+pub trait Order {
+    /// Compares keys, where the key which should be kept is less.
+    fn cmp<K: Ord>(a: &K, b: &K) -> Ordering;
+}
+
+/// Keeps the largest keys.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Largest;
+
+impl Order for Largest {
+    fn cmp<K: Ord>(a: &K, b: &K) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Keeps the smallest keys.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Smallest;
+
+impl Order for Smallest {
+    fn cmp<K: Ord>(a: &K, b: &K) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Extracts the key which `TopK` ranks elements by. Implemented for every `projection!` and
+/// closure from `&T` to an ordered key.
+pub trait SortKey<T> {
+    type Key: Ord;
+    fn key(&self, v: &T) -> Self::Key;
+}
+
+impl<T, U: Ord, F> SortKey<T> for F
+where
+    F: for<'a> Project<&'a T, Output = U>,
+{
+    type Key = U;
+    fn key(&self, v: &T) -> U {
+        self.project(v)
+    }
+}
+
+/// Ranks elements by a clone of themselves.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ByValue;
+
+impl<T: Clone> Project<&T> for ByValue {
+    type Output = T;
+    fn project(&self, v: &T) -> T {
+        v.clone()
+    }
+}
+
+/// Element kept by `TopK`, ordered so that the heap's greatest entry is the one to drop first.
+#[derive(Clone, Debug)]
+struct Entry<K, T, O> {
+    order: PhantomData<O>,
+    key: K,
+    /// Position in the stream, so that equal keys keep the earlier element.
+    seq: u64,
+    value: T,
+}
+
+impl<K: Ord, T, O: Order> Ord for Entry<K, T, O> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        O::cmp(&self.key, &other.key).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl<K: Ord, T, O: Order> PartialOrd for Entry<K, T, O> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T, O: Order> PartialEq for Entry<K, T, O> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord, T, O: Order> Eq for Entry<K, T, O> {}
+
+/// Exactly keeps the `K` elements of a stream with the largest or smallest keys, by the order
+/// `O`. Keys are taken by `F`, which defaults to the elements themselves, and can be a
+/// `projection!` to rank payloads by one of their fields, or a closure passed to `with_key`.
+/// Among elements with equal keys, the earliest are kept.
+///
+/// Elements are kept in a binary heap whose top is the next one to be dropped, so each element
+/// takes `O(log K)` time.
+#[derive(Clone, Debug)]
+pub struct TopK<T, const K: usize, O = Largest, F: SortKey<T> = ByValue> {
+    key: F,
+    heap: BinaryHeap<Entry<F::Key, T, O>>,
+    /// Number of elements seen.
+    seq: u64,
+}
+
+impl<T, const K: usize, O: Order, F: SortKey<T>> TopK<T, K, O, F> {
+    /// Creates a `TopK` which ranks elements by `key`, which can also be a closure.
+    pub fn with_key(key: F) -> Self {
+        assert_ne!(K, 0);
+        Self {
+            key,
+            heap: BinaryHeap::with_capacity(K),
+            seq: 0,
+        }
+    }
+    /// Processes `v`, also where the key is a closure, which `StreamProcessor` does not allow.
+    pub fn process(&mut self, v: T) {
+        let key = self.key.key(&v);
+        let entry = Entry {
+            order: PhantomData,
+            key,
+            seq: self.seq,
+            value: v,
+        };
+        self.seq += 1;
+        self.push(entry);
+    }
+    fn push(&mut self, entry: Entry<F::Key, T, O>) {
+        if self.heap.len() < K {
+            self.heap.push(entry);
+        } else if self.heap.peek().is_some_and(|worst| entry < *worst) {
+            self.heap.pop();
+            self.heap.push(entry);
+        }
+    }
+    /// Returns the kept elements, first ranked first.
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        let entries = self.heap.into_sorted_vec();
+        entries.into_iter().map(|e| e.value).collect()
+    }
+}
+
+impl<T: Clone, const K: usize, O: Order, F: SortKey<T> + Default> StreamProcessor<T>
+    for TopK<T, K, O, F>
+{
+    fn new() -> Self {
+        Self::with_key(F::default())
+    }
+    fn process(&mut self, v: T) {
+        TopK::process(self, v);
+    }
+    type Result = Vec<T>;
+    type Args = ();
+    /// Returns the kept elements, first ranked first.
+    fn query(&self, (): &()) -> Vec<T> {
+        let mut entries = self.heap.iter().collect::<Vec<_>>();
+        entries.sort_unstable();
+        entries.into_iter().map(|e| e.value.clone()).collect()
+    }
+}
+
+impl<T, const K: usize, O: Order, F: SortKey<T>> Merge for TopK<T, K, O, F> {
+    /// Treats the elements of `other` as coming after those of `self` when breaking ties.
+    fn merge(&mut self, other: Self) {
+        let offset = self.seq;
+        self.seq += other.seq;
+        for mut entry in other.heap {
+            entry.seq += offset;
+            self.push(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_topk {
+    use super::{Largest, Smallest, TopK};
+    use crate::{projection, Merge, SketchExt, StreamProcessor};
+
+    #[test]
+    fn largest_and_smallest() {
+        let xs = (0..1000u32).map(|i| i * 7919 % 1000);
+        assert_eq!(TopK::<_, 3>::apply(xs.clone(), &()), [999, 998, 997]);
+        assert_eq!(TopK::<_, 3, Smallest>::apply(xs, &()), [0, 1, 2]);
+        assert_eq!(TopK::<u8, 5>::apply(vec![2, 1].into_iter(), &()), [2, 1]);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Request {
+        latency: u32,
+        id: usize,
+    }
+    projection!(Latency: &Request => u32 = |r| r.latency);
+
+    #[test]
+    fn payloads_keep_earliest_ties() {
+        let reqs = (0..100).map(|id| Request {
+            latency: (id % 10) as u32,
+            id,
+        });
+        let slowest = TopK::<_, 3, Largest, Latency>::apply(reqs.clone(), &());
+        let ids = slowest.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids, [9, 19, 29]);
+
+        let (left, right) = reqs.partition::<Vec<_>, _>(|r| r.id >= 50);
+        let mut a = left.into_iter().sketch::<TopK<_, 3, Largest, Latency>>();
+        let b = right.into_iter().sketch::<TopK<_, 3, Largest, Latency>>();
+        // a saw 59, 69, 79 first, so they win the ties against b's 9, 19, 29
+        a.merge(b);
+        let ids = a.into_sorted_vec().iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids, [59, 69, 79]);
+    }
+
+    #[test]
+    fn closure_key() {
+        let mut closest = TopK::<i32, 2, Smallest, _>::with_key(|x: &i32| (x - 50).abs());
+        for x in (0..100).step_by(7) {
+            closest.process(x);
+        }
+        assert_eq!(closest.into_sorted_vec(), [49, 56]);
+    }
+
+    quickcheck! {
+      fn merged_is_sorted_prefix(xs: Vec<i16>, split: usize) -> bool {
+          let split = split.checked_rem(xs.len() + 1).unwrap_or(0);
+          let mut a = xs[..split].iter().copied().sketch::<TopK<_, 5, Smallest>>();
+          a.merge(xs[split..].iter().copied().sketch());
+          let mut sorted = xs;
+          sorted.sort_unstable();
+          sorted.truncate(5);
+          a.into_sorted_vec() == sorted
+      }
+    }
+}
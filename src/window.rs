@@ -0,0 +1,327 @@
+use super::{Merge, StreamProcessor};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem::replace;
+
+/// Measures windows in elements, so that a window of size `n` holds `n` consecutive elements.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Events;
+
+/// Measures windows in event time, for streams of `(timestamp, value)` pairs. Windows are
+/// aligned to multiples of their size, and elements older than the open window are counted in
+/// it instead of being dropped.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Timestamps;
+
+/// A finished window, which summarizes the elements at positions `start..end`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Closed<P> {
+    pub start: u64,
+    pub end: u64,
+    pub sketch: P,
+}
+
+/// Splits the stream into consecutive windows of `SIZE` events or time units, each summarized
+/// by its own processor. Queries go to the open window, and finished windows are queued until
+/// they are taken with `drain`.
+///
+/// Each window starts as a clone of one prototype, so windows share their hash functions. Windows
+/// which receive no elements are skipped.
+#[derive(Clone, Debug)]
+pub struct Tumbling<P, const SIZE: u64, C = Events> {
+    clock: PhantomData<C>,
+    prototype: P,
+    current: P,
+    /// Start of the open window, once it holds an element.
+    start: Option<u64>,
+    /// Position of the next element, when measuring in events.
+    next: u64,
+    closed: VecDeque<Closed<P>>,
+}
+
+impl<P: Clone, const SIZE: u64, C> Tumbling<P, SIZE, C> {
+    /// Creates windows which each start as a clone of `prototype`.
+    pub fn with_prototype(prototype: P) -> Self {
+        assert_ne!(SIZE, 0);
+        Self {
+            clock: PhantomData,
+            current: prototype.clone(),
+            prototype,
+            start: None,
+            next: 0,
+            closed: VecDeque::new(),
+        }
+    }
+    /// Moves to the window holding position `t`, closing the open window if it has ended.
+    fn advance(&mut self, t: u64) {
+        let start = t - t % SIZE;
+        match self.start {
+            Some(s) if start > s => {
+                let sketch = replace(&mut self.current, self.prototype.clone());
+                self.closed.push_back(Closed {
+                    start: s,
+                    end: s + SIZE,
+                    sketch,
+                });
+            }
+            Some(_) => return,
+            None => {}
+        }
+        self.start = Some(start);
+    }
+    /// Closes the open window early, as at the end of the stream.
+    pub fn flush(&mut self) {
+        if let Some(s) = self.start {
+            self.advance(s + SIZE);
+            self.start = None;
+        }
+    }
+    /// Takes every window which has closed so far, oldest first. They are kept until then, so
+    /// long streams should be drained regularly.
+    pub fn drain(&mut self) -> impl Iterator<Item = Closed<P>> + '_ {
+        self.closed.drain(..)
+    }
+    /// Processor of the open window.
+    pub fn current(&self) -> &P {
+        &self.current
+    }
+}
+
+impl<V, P: StreamProcessor<V> + Clone, const SIZE: u64> StreamProcessor<V>
+    for Tumbling<P, SIZE, Events>
+{
+    fn new() -> Self {
+        Self::with_prototype(P::new())
+    }
+    fn process(&mut self, v: V) {
+        self.advance(self.next);
+        self.next += 1;
+        self.current.process(v);
+    }
+    type Result = P::Result;
+    type Args = P::Args;
+    fn query(&self, args: &P::Args) -> P::Result {
+        self.current.query(args)
+    }
+}
+
+impl<V, P: StreamProcessor<V> + Clone, const SIZE: u64> StreamProcessor<(u64, V)>
+    for Tumbling<P, SIZE, Timestamps>
+{
+    fn new() -> Self {
+        Self::with_prototype(P::new())
+    }
+    fn process(&mut self, (t, v): (u64, V)) {
+        self.advance(t);
+        self.current.process(v);
+    }
+    type Result = P::Result;
+    type Args = P::Args;
+    fn query(&self, args: &P::Args) -> P::Result {
+        self.current.query(args)
+    }
+}
+
+/// Windows of `SIZE` events or time units which start every `HOP`, so that they overlap when
+/// `HOP < SIZE`. The stream is split into panes of length `HOP`, and each window is summarized
+/// by merging the panes it covers, so only `SIZE / HOP` panes are kept at a time.
+///
+/// A window closes when the stream passes its end, and is queued until taken with `drain`.
+/// Queries go to the window ending with the open pane. Panes are clones of one prototype, so
+/// that they can be merged.
+#[derive(Clone, Debug)]
+pub struct Hopping<P, const SIZE: u64, const HOP: u64, C = Events> {
+    clock: PhantomData<C>,
+    prototype: P,
+    current: P,
+    /// Start of the open pane, once it holds an element.
+    start: Option<u64>,
+    /// Position of the next element, when measuring in events.
+    next: u64,
+    /// Finished panes which are still covered by a window that has not closed.
+    panes: VecDeque<(u64, P)>,
+    closed: VecDeque<Closed<P>>,
+}
+
+impl<P: Merge + Clone, const SIZE: u64, const HOP: u64, C> Hopping<P, SIZE, HOP, C> {
+    /// Creates windows whose panes each start as a clone of `prototype`.
+    pub fn with_prototype(prototype: P) -> Self {
+        assert_ne!(HOP, 0);
+        assert_eq!(SIZE % HOP, 0, "window size must be a multiple of the hop");
+        Self {
+            clock: PhantomData,
+            current: prototype.clone(),
+            prototype,
+            start: None,
+            next: 0,
+            panes: VecDeque::new(),
+            closed: VecDeque::new(),
+        }
+    }
+    /// Merges the kept panes into one processor.
+    fn merged(&self) -> P {
+        let mut acc = self.prototype.clone();
+        for (_, p) in &self.panes {
+            acc.merge(p.clone());
+        }
+        acc
+    }
+    /// Moves to the pane holding position `t`, closing every window which ends before it.
+    fn advance(&mut self, t: u64) {
+        let start = t - t % HOP;
+        let prev = match self.start {
+            Some(s) if start > s => s,
+            Some(_) => return,
+            None => {
+                self.start = Some(start);
+                return;
+            }
+        };
+        self.start = Some(start);
+        let pane = replace(&mut self.current, self.prototype.clone());
+        self.panes.push_back((prev, pane));
+        let mut end = prev + HOP;
+        while end <= start {
+            while self.panes.front().is_some_and(|&(s, _)| s + SIZE < end) {
+                self.panes.pop_front();
+            }
+            if self.panes.is_empty() {
+                break;
+            }
+            self.closed.push_back(Closed {
+                start: end.saturating_sub(SIZE),
+                end,
+                sketch: self.merged(),
+            });
+            end += HOP;
+        }
+        while self.panes.front().is_some_and(|&(s, _)| s + SIZE <= start) {
+            self.panes.pop_front();
+        }
+    }
+    /// Closes every window holding an element, as at the end of the stream.
+    pub fn flush(&mut self) {
+        if let Some(s) = self.start {
+            self.advance(s + SIZE);
+            self.start = None;
+        }
+    }
+    /// Takes every window which has closed so far, oldest first. They are kept until then, so
+    /// long streams should be drained regularly.
+    pub fn drain(&mut self) -> impl Iterator<Item = Closed<P>> + '_ {
+        self.closed.drain(..)
+    }
+    /// Processor of the window ending with the open pane, merged from its panes.
+    pub fn current(&self) -> P {
+        let mut acc = self.merged();
+        acc.merge(self.current.clone());
+        acc
+    }
+}
+
+impl<V, P, const SIZE: u64, const HOP: u64> StreamProcessor<V> for Hopping<P, SIZE, HOP, Events>
+where
+    P: StreamProcessor<V> + Merge + Clone,
+{
+    fn new() -> Self {
+        Self::with_prototype(P::new())
+    }
+    fn process(&mut self, v: V) {
+        self.advance(self.next);
+        self.next += 1;
+        self.current.process(v);
+    }
+    type Result = P::Result;
+    type Args = P::Args;
+    fn query(&self, args: &P::Args) -> P::Result {
+        self.current().query(args)
+    }
+}
+
+impl<V, P, const SIZE: u64, const HOP: u64> StreamProcessor<(u64, V)>
+    for Hopping<P, SIZE, HOP, Timestamps>
+where
+    P: StreamProcessor<V> + Merge + Clone,
+{
+    fn new() -> Self {
+        Self::with_prototype(P::new())
+    }
+    fn process(&mut self, (t, v): (u64, V)) {
+        self.advance(t);
+        self.current.process(v);
+    }
+    type Result = P::Result;
+    type Args = P::Args;
+    fn query(&self, args: &P::Args) -> P::Result {
+        self.current().query(args)
+    }
+}
+
+#[cfg(test)]
+mod test_window {
+    use super::{Hopping, Timestamps, Tumbling};
+    use crate::count::ExactCounter;
+    use crate::distinct::HyperLogLog;
+    use crate::StreamProcessor;
+
+    fn count(c: &ExactCounter) -> usize {
+        StreamProcessor::<u32>::query(c, &())
+    }
+
+    #[test]
+    fn tumbling_events() {
+        let mut w = Tumbling::<HyperLogLog<u32>, 100>::new();
+        for i in 0..250 {
+            w.process(i % 10 + 10 * (i / 100));
+        }
+        let closed = w.drain().collect::<Vec<_>>();
+        assert_eq!(closed.len(), 2);
+        assert_eq!((closed[1].start, closed[1].end), (100, 200));
+        // small counts are exact, unless two users share a register
+        assert!((9..=10).contains(&closed[1].sketch.query(&())));
+        assert!((9..=10).contains(&w.query(&())));
+        w.flush();
+        assert_eq!(w.drain().map(|c| c.start).collect::<Vec<_>>(), [200]);
+    }
+
+    #[test]
+    fn tumbling_timestamps_skip_empty() {
+        let mut w: Tumbling<ExactCounter, 60, Timestamps> = StreamProcessor::<(u64, u32)>::new();
+        for t in [0, 10, 59, 60, 300, 301, 302] {
+            w.process((t, 0u32));
+        }
+        w.flush();
+        let closed = w
+            .drain()
+            .map(|c| (c.start, count(&c.sketch)))
+            .collect::<Vec<_>>();
+        assert_eq!(closed, [(0, 3), (60, 1), (300, 3)]);
+    }
+
+    #[test]
+    fn hopping_events() {
+        let mut w: Hopping<ExactCounter, 4, 2> = StreamProcessor::<u32>::new();
+        for i in 0..10u32 {
+            w.process(i);
+        }
+        assert_eq!(count(&w.current()), 4);
+        w.flush();
+        let closed = w
+            .drain()
+            .map(|c| (c.end, count(&c.sketch)))
+            .collect::<Vec<_>>();
+        assert_eq!(closed, [(2, 2), (4, 4), (6, 4), (8, 4), (10, 4), (12, 2)]);
+    }
+
+    #[test]
+    fn hopping_timestamps_gap() {
+        let mut w: Hopping<ExactCounter, 3, 1, Timestamps> = StreamProcessor::<(u64, u32)>::new();
+        w.process((0, 0u32));
+        w.process((5, 0u32));
+        let closed = w
+            .drain()
+            .map(|c| (c.start, c.end, count(&c.sketch)))
+            .collect::<Vec<_>>();
+        assert_eq!(closed, [(0, 1, 1), (0, 2, 1), (0, 3, 1)]);
+    }
+}